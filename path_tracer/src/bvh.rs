@@ -0,0 +1,234 @@
+use crate::geometry::Aabb;
+use crate::ray_hit::HitRecord;
+use crate::ray_hit::Ray;
+use crate::utils::Float;
+use crate::utils::INFIN;
+use crate::vector::Vec3;
+
+const BUCKETS: usize = 12;
+const MAX_LEAF_SIZE: usize = 4;
+const TRAVERSAL_COST: Float = 0.125;
+
+const STACK_SIZE: usize = 64;
+// past this depth nodes are split at the median, which keeps the tree shallow enough for the stack
+const MAX_SAH_DEPTH: usize = 32;
+
+// only used while building, gets flattened into linear nodes straight after
+enum BuildNode {
+    Leaf { bounds: Aabb, first: usize, count: usize },
+    Interior { bounds: Aabb, axis: usize, left: Box<BuildNode>, right: Box<BuildNode> },
+}
+
+// 32 bytes so two nodes share a cache line, children are stored depth first so the left child is
+// always the next node and only the second child needs an offset
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct LinearNode {
+    bounds: Aabb,
+    // first primitive for leaves, second child for interior nodes
+    offset: u32,
+    count: u16,
+    axis: u8,
+    _pad: u8,
+}
+
+const _: () = assert!(std::mem::size_of::<LinearNode>() == 32);
+
+// the tree only stores indices into whatever primitive list it was built over, so the owner
+// does the actual intersection through the closure handed to hit
+pub(crate) struct Bvh {
+    nodes: Vec<LinearNode>,
+    indices: Vec<u32>,
+}
+
+impl Bvh {
+    pub fn build(bounds: &[Aabb]) -> Self {
+        let centroids: Vec<Vec3> = bounds.iter().map(|bound| bound.centroid()).collect();
+        let mut indices: Vec<usize> = (0..bounds.len()).collect();
+        let mut nodes = Vec::new();
+        if !bounds.is_empty() {
+            let root = build_recursive(bounds, &centroids, &mut indices, 0, 0);
+            flatten(&root, &mut nodes);
+        }
+
+        Bvh { nodes, indices: indices.into_iter().map(|index| index as u32).collect() }
+    }
+
+    pub fn hit<F>(&self, ray: &Ray, record: &mut HitRecord, hit_primitive: F) -> bool
+    where
+        F: Fn(usize, &mut HitRecord) -> bool,
+    {
+        if self.nodes.is_empty() {
+            return false;
+        }
+
+        let inverse_direction = ray.inverse_direction();
+        let direction_negative =
+            [inverse_direction.x < 0., inverse_direction.y < 0., inverse_direction.z < 0.];
+
+        let mut hit_anything = false;
+        let mut stack = [0u32; STACK_SIZE];
+        let mut stack_size = 0;
+        let mut current = 0;
+        loop {
+            let node = &self.nodes[current];
+            if node.bounds.hit(ray, &inverse_direction, &record.interval).is_some() {
+                if node.count > 0 {
+                    let first = node.offset as usize;
+                    for &index in &self.indices[first..first + node.count as usize] {
+                        if hit_primitive(index as usize, record) {
+                            hit_anything = true;
+                        }
+                    }
+                }
+                else {
+                    // push the far child and carry on with the near one so closer hits shrink the
+                    // interval before the far side is tested
+                    let (near, far) = if direction_negative[node.axis as usize] {
+                        (node.offset, current as u32 + 1)
+                    }
+                    else {
+                        (current as u32 + 1, node.offset)
+                    };
+                    stack[stack_size] = far;
+                    stack_size += 1;
+                    current = near as usize;
+                    continue;
+                }
+            }
+
+            if stack_size == 0 {
+                break;
+            }
+            stack_size -= 1;
+            current = stack[stack_size] as usize;
+        }
+
+        hit_anything
+    }
+}
+
+fn flatten(node: &BuildNode, nodes: &mut Vec<LinearNode>) -> u32 {
+    let index = nodes.len();
+    match node {
+        BuildNode::Leaf { bounds, first, count } => {
+            nodes.push(LinearNode {
+                bounds: *bounds,
+                offset: *first as u32,
+                count: *count as u16,
+                axis: 0,
+                _pad: 0,
+            });
+        }
+        BuildNode::Interior { bounds, axis, left, right } => {
+            nodes.push(LinearNode { bounds: *bounds, offset: 0, count: 0, axis: *axis as u8, _pad: 0 });
+            flatten(left, nodes);
+            nodes[index].offset = flatten(right, nodes);
+        }
+    }
+
+    index as u32
+}
+
+fn build_recursive(
+    bounds: &[Aabb],
+    centroids: &[Vec3],
+    indices: &mut [usize],
+    first: usize,
+    depth: usize,
+) -> BuildNode {
+    let node_bounds = indices.iter().fold(Aabb::empty(), |acc, &index| acc.union(&bounds[index]));
+    let centroid_bounds = indices.iter().fold(Aabb::empty(), |acc, &index| acc.grow(&centroids[index]));
+    let count = indices.len();
+    if count == 1 {
+        return BuildNode::Leaf { bounds: node_bounds, first, count };
+    }
+
+    let axis = centroid_bounds.longest_axis();
+    let axis_min = centroid_bounds.min.axis(axis);
+    let extent = centroid_bounds.max.axis(axis) - axis_min;
+    let middle = if extent <= 0. || depth >= MAX_SAH_DEPTH {
+        if count <= MAX_LEAF_SIZE {
+            return BuildNode::Leaf { bounds: node_bounds, first, count };
+        }
+        median_split(centroids, indices, axis)
+    }
+    else {
+        let bucket_of = |index: usize| {
+            let relative = (centroids[index].axis(axis) - axis_min) / extent;
+            ((relative * BUCKETS as Float) as usize).min(BUCKETS - 1)
+        };
+
+        let mut bucket_counts = [0usize; BUCKETS];
+        let mut bucket_bounds = [Aabb::empty(); BUCKETS];
+        for &index in indices.iter() {
+            let bucket = bucket_of(index);
+            bucket_counts[bucket] += 1;
+            bucket_bounds[bucket] = bucket_bounds[bucket].union(&bounds[index]);
+        }
+
+        // surface area heuristic: expected cost of splitting after each bucket boundary
+        let mut best_cost = INFIN;
+        let mut best_split = 0;
+        for split in 0..BUCKETS - 1 {
+            let (mut left_bounds, mut left_count) = (Aabb::empty(), 0);
+            let (mut right_bounds, mut right_count) = (Aabb::empty(), 0);
+            for bucket in 0..=split {
+                left_bounds = left_bounds.union(&bucket_bounds[bucket]);
+                left_count += bucket_counts[bucket];
+            }
+            for bucket in split + 1..BUCKETS {
+                right_bounds = right_bounds.union(&bucket_bounds[bucket]);
+                right_count += bucket_counts[bucket];
+            }
+
+            let cost = TRAVERSAL_COST
+                + (left_count as Float * left_bounds.surface_area()
+                    + right_count as Float * right_bounds.surface_area())
+                    / node_bounds.surface_area().max(Float::MIN_POSITIVE);
+            if cost < best_cost {
+                best_cost = cost;
+                best_split = split;
+            }
+        }
+
+        let leaf_cost = count as Float;
+        if count <= MAX_LEAF_SIZE && leaf_cost <= best_cost {
+            return BuildNode::Leaf { bounds: node_bounds, first, count };
+        }
+
+        match partition(indices, |index| bucket_of(index) <= best_split) {
+            0 => median_split(centroids, indices, axis),
+            middle if middle == count => median_split(centroids, indices, axis),
+            middle => middle,
+        }
+    };
+
+    let (left_indices, right_indices) = indices.split_at_mut(middle);
+    let left = build_recursive(bounds, centroids, left_indices, first, depth + 1);
+    let right = build_recursive(bounds, centroids, right_indices, first + middle, depth + 1);
+
+    BuildNode::Interior { bounds: node_bounds, axis, left: Box::new(left), right: Box::new(right) }
+}
+
+fn median_split(centroids: &[Vec3], indices: &mut [usize], axis: usize) -> usize {
+    let middle = indices.len() / 2;
+    indices
+        .select_nth_unstable_by(middle, |&a, &b| centroids[a].axis(axis).total_cmp(&centroids[b].axis(axis)));
+    middle
+}
+
+fn partition<P>(indices: &mut [usize], predicate: P) -> usize
+where
+    P: Fn(usize) -> bool,
+{
+    let mut middle = 0;
+    for i in 0..indices.len() {
+        if predicate(indices[i]) {
+            indices.swap(i, middle);
+            middle += 1;
+        }
+    }
+
+    middle
+}
@@ -1,27 +1,54 @@
 use std::fs::File;
 use std::io;
-use std::io::BufWriter;
-use std::io::Write;
 use std::sync::atomic::AtomicUsize;
 use std::sync::atomic::Ordering;
-use std::sync::Arc;
-use std::thread;
-use std::time::Duration;
 
 use rayon::iter::IndexedParallelIterator;
+use rayon::iter::IntoParallelIterator;
 use rayon::iter::IntoParallelRefMutIterator;
 use rayon::iter::ParallelIterator;
 use rayon::slice::ParallelSliceMut;
-
+use serde::Deserialize;
+
+use crate::hdr_buffer::HdrBuffer;
+use crate::output;
+use crate::output::BitDepth;
+use crate::output::Format;
+use crate::output::Layer;
+use crate::ray_hit::HitRecord;
 use crate::ray_hit::Ray;
 use crate::scene::Scene;
-use crate::utils::packed_color;
-use crate::utils::unpack_color;
-use crate::utils::write_pixel_gammcorr;
+use crate::tonemap::Tonemap;
+use crate::utils::seed_pixel;
 use crate::utils::Float;
 use crate::utils::Int;
+use crate::utils::PI;
 use crate::vector::Vec3;
-use crate::Buffer;
+
+// fov is the vertical angle for perspective and the angle across the image height for fisheye,
+// orthographic shows as much as perspective would at the focus distance, and equirectangular
+// always covers the full sphere so it wants a 2:1 image
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Projection {
+    #[default]
+    Perspective,
+    Orthographic,
+    Fisheye,
+    Equirectangular,
+}
+
+impl Projection {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "perspective" => Some(Projection::Perspective),
+            "orthographic" => Some(Projection::Orthographic),
+            "fisheye" => Some(Projection::Fisheye),
+            "equirectangular" => Some(Projection::Equirectangular),
+            _ => None,
+        }
+    }
+}
 
 #[derive(Default, Debug)]
 pub struct Camera {
@@ -30,6 +57,11 @@ pub struct Camera {
     pub aspect_ratio: Float,
     pub fov: Float,
     pub focal_length: Float,
+    pub projection: Projection,
+    // radius of the lens, zero keeps the old pinhole, and the distance along the view direction
+    // that stays sharp
+    pub aperture: Float,
+    pub focus_distance: Float,
 
     pub position: Vec3,
     pub front: Vec3,
@@ -48,14 +80,74 @@ pub struct Camera {
     pub pixel_dv: Vec3,
 
     pub samples: Int,
-    pub max_recursive_depth: Int,
+    pub max_depth: Option<Int>,
+    pub roulette_depth: Int,
     pub denoise_iters: Int,
+    pub seed: Option<u64>,
+    pub tonemap: Tonemap,
 }
 
 impl Camera {
+    // aims a fresh camera from eye at target and focuses on the target, the size and render
+    // settings still have to be filled in before build_params
+    pub fn look_at(eye: Vec3, target: Vec3, up: Vec3, fov: Float) -> Self {
+        let mut camera =
+            Camera { focal_length: 1., focus_distance: (target - eye).length(), ..Default::default() };
+        camera.aim(eye, target, up, fov);
+
+        camera
+    }
+
+    // why eye, target and up can't frame a shot, if they can't, up is expected to be non zero
+    pub fn aim_problem(eye: Vec3, target: Vec3, up: Vec3) -> Option<&'static str> {
+        let view = target - eye;
+        if view.near_zero() {
+            return Some("`target` can't be the camera position");
+        }
+        if view.normalized().cross_product(&up.normalized()).near_zero() {
+            return Some("`up` can't point along the view direction");
+        }
+
+        None
+    }
+
+    // derives yaw and pitch for the given framing, so the preview controls carry on from it
+    pub fn aim(&mut self, eye: Vec3, target: Vec3, up: Vec3, fov: Float) {
+        self.position = eye;
+        self.world_up = up.normalized();
+        self.fov = fov;
+
+        // the camera looks down -front, so front points from the target back to the eye
+        let front = (eye - target).normalized();
+        let (axis_x, axis_z) = self.yaw_axes();
+        self.pitch = front.inner_product(&self.world_up).clamp(-1., 1.).asin();
+        self.yaw = front.inner_product(&axis_z).atan2(front.inner_product(&axis_x));
+        self.update_vectors();
+    }
+
+    // the framing as a [camera] table that can be pasted into a scene file, the target goes at the
+    // focus distance so it lands on whatever is sharp
+    pub fn pose(&self) -> String {
+        let distance = if self.focus_distance > 0. { self.focus_distance } else { 1. };
+        let target = self.position - self.front * distance;
+        let toml_vec = |vector: Vec3| format!("[{:?}, {:?}, {:?}]", vector.x, vector.y, vector.z);
+        format!(
+            "[camera]\nposition = {}\ntarget = {}\nup = {}\nfov = {:?}\naperture = {:?}\nfocus_distance = {:?}",
+            toml_vec(self.position),
+            toml_vec(target),
+            toml_vec(self.world_up),
+            self.fov,
+            self.aperture,
+            distance,
+        )
+    }
+
     pub fn build_params(&mut self) {
         self.aspect_ratio = self.width as Float / self.height as Float;
-        self.world_up = Vec3::build(0., 1., 0.);
+        // only cameras that never got an up vector fall back to y
+        if self.world_up.near_zero() {
+            self.world_up = Vec3::build(0., 1., 0.);
+        }
         self.front = Vec3::build(0., 0., 1.).normalized();
         self.set_viewport();
     }
@@ -79,136 +171,232 @@ impl Camera {
     }
 
     pub fn update_vectors(&mut self) {
-        let front = Vec3::build(
-            self.yaw.cos() * self.pitch.cos(),
-            self.pitch.sin(),
-            self.yaw.sin() * self.pitch.cos(),
-        );
+        let (axis_x, axis_z) = self.yaw_axes();
+        let front = axis_x * (self.yaw.cos() * self.pitch.cos())
+            + self.world_up * self.pitch.sin()
+            + axis_z * (self.yaw.sin() * self.pitch.cos());
         self.front = front.normalized();
         self.right = self.world_up.cross_product(&self.front).normalized();
         self.up = self.front.cross_product(&self.right).normalized();
     }
 
+    // yaw turns around world_up starting from axis_x towards axis_z, for the usual y up these are
+    // just x and z
+    fn yaw_axes(&self) -> (Vec3, Vec3) {
+        let reference =
+            if self.world_up.x.abs() < 0.9 { Vec3::build(1., 0., 0.) } else { Vec3::build(0., 0., 1.) };
+        let axis_x = (reference - self.world_up * reference.inner_product(&self.world_up)).normalized();
+
+        (axis_x, axis_x.cross_product(&self.world_up))
+    }
+
     pub fn get_ray(&self, x: Int, y: Int) -> Ray {
         let offset = Vec3::random();
-        let pixel_sample = self.pixel_top_left
-            + (self.pixel_du * (offset.x + x as Float))
-            + (self.pixel_dv * (offset.y + y as Float));
+        // continuous pixel coordinates with the pixel centers on whole numbers
+        let (sample_x, sample_y) = (offset.x + x as Float, offset.y + y as Float);
+        // the cone covers one pixel, as an angle for the projections that fan out from a point and as
+        // a width for orthographic
+        let pixel = self.pixel_dv.length();
+        match self.projection {
+            Projection::Perspective => {
+                self.perspective_ray(sample_x, sample_y).with_cone(0., pixel / self.focal_length)
+            }
+            Projection::Orthographic => self
+                .orthographic_ray(sample_x, sample_y)
+                .with_cone(pixel * self.focus_distance / self.focal_length, 0.),
+            Projection::Fisheye => Ray::build(self.position, self.fisheye_direction(sample_x, sample_y))
+                .with_cone(0., self.fov.to_radians() / self.height as Float),
+            Projection::Equirectangular => {
+                Ray::build(self.position, self.equirectangular_direction(sample_x, sample_y))
+                    .with_cone(0., PI / self.height as Float)
+            }
+        }
+    }
+
+    fn viewport_point(&self, sample_x: Float, sample_y: Float) -> Vec3 {
+        self.pixel_top_left + (self.pixel_du * sample_x) + (self.pixel_dv * sample_y)
+    }
+
+    fn perspective_ray(&self, sample_x: Float, sample_y: Float) -> Ray {
+        let pixel_sample = self.viewport_point(sample_x, sample_y);
         let ray_direction = pixel_sample - self.position;
+        if self.aperture <= 0. {
+            return Ray::build(self.position, ray_direction);
+        }
+
+        // the pixel sample sits focal_length in front of the pinhole, so scaling the pinhole ray out
+        // to the focus distance gives the point every ray through the lens has to pass
+        let focus_point = self.position + ray_direction * (self.focus_distance / self.focal_length);
+        let lens = Vec3::random_in_unit_disk() * self.aperture;
+        let origin = self.position + self.right * lens.x + self.up * lens.y;
+
+        Ray::build(origin, focus_point - origin)
+    }
+
+    // parallel rays starting on the camera plane, spread over the viewport scaled out to the focus
+    // distance
+    fn orthographic_ray(&self, sample_x: Float, sample_y: Float) -> Ray {
+        let viewport_center = self.position - self.front * self.focal_length;
+        let lateral = self.viewport_point(sample_x, sample_y) - viewport_center;
+        let origin = self.position + lateral * (self.focus_distance / self.focal_length);
 
-        Ray::build(self.position, ray_direction)
+        Ray::build(origin, -self.front)
     }
 
-    pub fn render_to_buffer_par(&self, buffer: &mut Buffer, scene: &Scene) {
+    // equidistant, the angle away from the view direction grows linearly with the distance from the
+    // image center
+    fn fisheye_direction(&self, sample_x: Float, sample_y: Float) -> Vec3 {
+        let screen_x = (2. * (sample_x + 0.5) / self.width as Float - 1.) * self.aspect_ratio;
+        let screen_y = 1. - 2. * (sample_y + 0.5) / self.height as Float;
+        let radius = (screen_x * screen_x + screen_y * screen_y).sqrt();
+        if radius == 0. {
+            return -self.front;
+        }
+        let (sin, cos) = (radius * self.fov.to_radians() * 0.5).sin_cos();
+
+        -self.front * cos + (self.right * screen_x + self.up * screen_y) * (sin / radius)
+    }
+
+    // longitude across the width and latitude down the height, the image center looks straight ahead
+    fn equirectangular_direction(&self, sample_x: Float, sample_y: Float) -> Vec3 {
+        let longitude = ((sample_x + 0.5) / self.width as Float - 0.5) * 2. * PI;
+        let latitude = (0.5 - (sample_y + 0.5) / self.height as Float) * PI;
+        let (sin_longitude, cos_longitude) = longitude.sin_cos();
+
+        (-self.front * cos_longitude + self.right * sin_longitude) * latitude.cos() + self.up * latitude.sin()
+    }
+
+    // focuses on whatever is under the center of the screen, nothing changes when that is the sky
+    pub fn autofocus(&mut self, scene: &Scene) -> Option<Float> {
+        let mut record = HitRecord::new();
+        if !scene.hit(&Ray::build(self.position, -self.front), &mut record) {
+            return None;
+        }
+        self.focus_distance = record.intersection_time;
+
+        Some(self.focus_distance)
+    }
+
+    // without a seed every thread just keeps drawing from its own randomly seeded generator
+    fn seed_pixel(&self, index: usize, samples: u32) {
+        if let Some(seed) = self.seed {
+            seed_pixel(seed, index, samples);
+        }
+    }
+
+    pub fn render_to_buffer_par(&self, buffer: &mut HdrBuffer, scene: &Scene) {
         buffer.pixels.par_iter_mut().enumerate().for_each(|(idx, pixel)| {
             let x = (idx % buffer.width) as i32;
             let y = (idx / buffer.width) as i32;
+            self.seed_pixel(idx, pixel.samples);
 
             let mut pixel_color = Vec3::zeros();
             (0..self.samples).for_each(|_| {
                 let ray = self.get_ray(x, y);
-                pixel_color += scene.get_color(&ray, self.max_recursive_depth);
+                pixel_color += scene.get_color(&ray, self.max_depth, self.roulette_depth);
             });
 
-            *pixel = packed_color(pixel_color / self.samples as Float);
+            pixel.add(pixel_color, self.samples as u32);
         });
     }
 
-    pub fn render_to_file_par(&mut self, file: &mut File, scene: &Scene) -> io::Result<()> {
-        let total_pixels = (self.width * self.height) as usize;
-        let progress_counter = Arc::new(AtomicUsize::new(0));
-        let progress_clone = Arc::clone(&progress_counter);
-        let progress_thread = thread::spawn(move || loop {
-            let done = progress_clone.load(Ordering::Relaxed);
-            let percent = done as Float / total_pixels as Float * 100.;
-            println!("progress: {:.2}%", percent);
+    // renders a full frame into a fresh buffer without denoising or any output, for callers that
+    // want the linear radiance in memory
+    pub fn render(&self, scene: &Scene) -> HdrBuffer {
+        let mut buffer = HdrBuffer::build(self.height as usize, self.width as usize);
+        self.render_rows_par(&mut buffer, scene, &AtomicUsize::new(0));
 
-            if done >= total_pixels {
-                break;
-            }
-
-            thread::sleep(Duration::from_millis(1000));
-        });
+        buffer
+    }
 
-        let mut buffer = Buffer::build(self.height as usize, self.width as usize);
-        let chunk_size = buffer.width;
-        buffer.pixels.par_chunks_mut(chunk_size).enumerate().for_each(|(chunk_idx, chunk)| {
-            let start_idx = chunk_idx * chunk_size;
-            let chunk_length = chunk.len();
+    // splits the work by rows and counts finished pixels into progress as it goes
+    fn render_rows_par(&self, buffer: &mut HdrBuffer, scene: &Scene, progress: &AtomicUsize) {
+        let width = buffer.width;
+        buffer.pixels.par_chunks_mut(width).enumerate().for_each(|(row, chunk)| {
+            let start_idx = row * width;
             for (i, pixel) in chunk.iter_mut().enumerate() {
                 let idx = start_idx + i;
-                let x = (idx % buffer.width) as i32;
-                let y = (idx / buffer.width) as i32;
+                let x = (idx % width) as i32;
+                let y = (idx / width) as i32;
+                self.seed_pixel(idx, pixel.samples);
                 let mut pixel_color = Vec3::zeros();
                 (0..self.samples).for_each(|_| {
                     let ray = self.get_ray(x, y);
-                    pixel_color += scene.get_color(&ray, self.max_recursive_depth);
+                    pixel_color += scene.get_color(&ray, self.max_depth, self.roulette_depth);
                 });
 
-                *pixel = packed_color(pixel_color / self.samples as Float);
+                pixel.add(pixel_color, self.samples as u32);
             }
-            progress_counter.fetch_add(chunk_length, Ordering::Relaxed);
+            progress.fetch_add(chunk.len(), Ordering::Relaxed);
         });
+    }
 
-        progress_thread.join().unwrap();
+    // renders a full frame, then denoises it, progress counts finished pixels up to width * height
+    // so the caller can report on it from another thread
+    pub fn render_par(&self, scene: &Scene, progress: &AtomicUsize) -> HdrBuffer {
+        let mut buffer = HdrBuffer::build(self.height as usize, self.width as usize);
+        self.render_rows_par(&mut buffer, scene, progress);
 
         buffer.bilateral_denoise(self.denoise_iters);
 
-        let mut writer = BufWriter::with_capacity((self.width * self.height * 12) as usize, file);
-        writeln!(writer, "P3\n{} {}\n255", self.width, self.height)?;
-        for y in 0..self.height as usize {
-            for x in 0..self.width as usize {
-                let packed_color = buffer.pixels[y * buffer.width + x];
-                let pixel_color = unpack_color(packed_color);
-                write_pixel_gammcorr(&mut writer, pixel_color)?;
-            }
-        }
+        buffer
+    }
+
+    // first hit albedo and normal averaged over the same pixel footprint as the render, the usual
+    // guide layers for an external denoiser
+    pub fn render_aovs(&self, scene: &Scene) -> Vec<Layer> {
+        let width = self.width as usize;
+        let (albedo, normal): (Vec<Vec3>, Vec<Vec3>) = (0..(self.width * self.height) as usize)
+            .into_par_iter()
+            .map(|idx| {
+                self.seed_pixel(idx, 0);
+                let (mut albedo, mut normal) = (Vec3::zeros(), Vec3::zeros());
+                (0..self.samples).for_each(|_| {
+                    let ray = self.get_ray((idx % width) as i32, (idx / width) as i32);
+                    let (surface_albedo, surface_normal) = scene.get_surface(&ray);
+                    albedo += surface_albedo;
+                    normal += surface_normal;
+                });
+                (albedo / self.samples as Float, normal / self.samples as Float)
+            })
+            .unzip();
+
+        vec![
+            Layer { name: "albedo".to_string(), pixels: albedo },
+            Layer { name: "normal".to_string(), pixels: normal },
+        ]
+    }
+
+    pub fn render_to_file_par(
+        &self,
+        file: &mut File,
+        scene: &Scene,
+        format: Format,
+        depth: BitDepth,
+    ) -> io::Result<()> {
+        let buffer = self.render_par(scene, &AtomicUsize::new(0));
 
-        Ok(())
+        output::write(&buffer, &[], file, format, depth, &self.tonemap)
     }
 
-    #[allow(dead_code)]
-    pub fn render_to_buffer(&self, buffer: &mut Buffer, scene: &Scene) {
+    pub fn render_to_buffer(&self, buffer: &mut HdrBuffer, scene: &Scene) {
         for y in 0..buffer.height {
             for x in 0..buffer.width {
+                self.seed_pixel(y * buffer.width + x, buffer.pixels[y * buffer.width + x].samples);
                 let mut pixel_color = Vec3::zeros();
                 (0..self.samples).for_each(|_| {
                     let ray = self.get_ray(x as i32, y as i32);
-                    pixel_color += scene.get_color(&ray, self.max_recursive_depth);
+                    pixel_color += scene.get_color(&ray, self.max_depth, self.roulette_depth);
                 });
-                buffer.write_pixel(x, y, pixel_color / self.samples as Float);
+                buffer.pixels[y * buffer.width + x].add(pixel_color, self.samples as u32);
             }
         }
     }
 
-    #[allow(dead_code)]
     pub fn render_to_file(&self, file: &mut File, scene: &Scene) -> io::Result<()> {
-        let printerval = self.height / 100;
+        let mut buffer = HdrBuffer::build(self.height as usize, self.width as usize);
+        self.render_to_buffer(&mut buffer, scene);
 
-        let mut writer = BufWriter::with_capacity((self.width * self.height * 12) as usize, file);
-        writeln!(writer, "P3\n{} {}\n255", self.width, self.height)?;
-        for y in 0..self.height {
-            for x in 0..self.width {
-                let mut pixel_color = Vec3::zeros();
-                (0..self.samples).for_each(|_| {
-                    let ray = self.get_ray(x, y);
-                    pixel_color += scene.get_color(&ray, self.max_recursive_depth);
-                });
-                write_pixel_gammcorr(&mut writer, pixel_color / self.samples as Float)?;
-            }
-            writeln!(writer)?;
-            if y % printerval == 0 {
-                println!("progress: {:.1}%", y as f32 / self.height as Float * 100.);
-            }
-        }
-        writer.flush()?;
-
-        Ok(())
+        output::write(&buffer, &[], file, Format::Ppm, BitDepth::Eight, &self.tonemap)
     }
 }
-
-// let yaw = -1.559;
-// let pitch = 0.1459;
-// let look_at = Vec3::build(-0.0279, 0.1455, -0.9889);
-// let position = Vec3::build(2.009, 9.556, -20.757);
-// let front = look_at.normalized();
@@ -0,0 +1,414 @@
+use std::fmt;
+use std::path::Path;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use path_tracer::camera::Projection;
+use path_tracer::debug_scenes;
+use path_tracer::output::BitDepth;
+use path_tracer::output::Format;
+use path_tracer::tonemap::Operator;
+use path_tracer::utils::Float;
+use path_tracer::utils::Int;
+use path_tracer::Camera;
+use path_tracer::Vec3;
+
+pub const USAGE: &str = "usage: path_tracer [options]
+
+options:
+  --scene <name|file>     built in scene (cornell_basic, cornell_room, furnace) or a .toml scene
+                          file (default cornell_basic)
+  -o, --output <path>     image to write (default ../images/dump.ppm)
+  --format <format>       output format: ppm, png, pfm, exr (default taken from the output extension)
+  --bit-depth <bits>      bits per channel: 8 or 16 for ppm and png (default 8), 16 for half or 32
+                          for float exr (default 16), pfm is always 32
+  --aovs                  also write first hit albedo and normal layers, exr only
+
+the options below override what the scene file sets, built in scenes use the defaults shown
+  --width <pixels>        width of the final render (default 1920)
+  --height <pixels>       height of the final render (default 1080)
+  --spp <samples>         samples per pixel of the final render (default 30)
+  --max-depth <bounces>   hard bounce limit, or `none` to rely on russian roulette (default none)
+  --denoise <iters>       bilateral denoise iterations (default 1)
+  --seed <number>         seed for reproducible renders (default random)
+  --position <x,y,z>      camera position (default 0,2,7)
+  --yaw <radians>         camera yaw (default pi / 2)
+  --pitch <radians>       camera pitch (default 0)
+  --target <x,y,z>        point to aim the camera at, replaces yaw and pitch
+  --up <x,y,z>            camera up vector (default 0,1,0)
+  --fov <degrees>         vertical field of view (default 55)
+  --projection <kind>     perspective, orthographic, fisheye or equirectangular (default perspective)
+  --aperture <radius>     lens radius for depth of field, 0 for a pinhole (default 0)
+  --focus-distance <d>    distance from the camera that stays sharp (default 7, or the distance to
+                          --target when that is given)
+  --exposure <stops>      exposure applied before tonemapping (default 0)
+  --tonemap <operator>    clamp, reinhard, extended-reinhard, aces-fitted or filmic (default clamp),
+                          only ppm, png and the preview are tonemapped
+  --white-point <value>   radiance extended reinhard maps to white (default 4)
+  --headless              skip the preview window and render straight away
+  -h, --help              print this message";
+
+#[derive(Debug)]
+pub enum CliError {
+    Help,
+    UnknownOption(String),
+    MissingValue(String),
+    InvalidValue { option: String, value: String },
+    UnknownScene(String),
+    UnknownFormat(String),
+    UnsupportedDepth(Format, u32),
+    LayersNeedExr,
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::Help => write!(f, "help requested"),
+            CliError::UnknownOption(option) => write!(f, "unknown option `{}`", option),
+            CliError::MissingValue(option) => write!(f, "option `{}` needs a value", option),
+            CliError::InvalidValue { option, value } => {
+                write!(f, "invalid value `{}` for option `{}`", value, option)
+            }
+            CliError::UnknownScene(name) => write!(f, "unknown scene `{}`", name),
+            CliError::UnknownFormat(format) => write!(f, "unknown output format `{}`", format),
+            CliError::UnsupportedDepth(format, bits) => {
+                write!(f, "{} output can't be written with {} bits per channel", format.name(), bits)
+            }
+            CliError::LayersNeedExr => write!(f, "extra layers can only be written to exr"),
+        }
+    }
+}
+
+impl std::error::Error for CliError {}
+
+#[derive(Debug)]
+pub enum SceneSource {
+    Builtin(String),
+    File(PathBuf),
+}
+
+// everything left as None keeps whatever the scene itself asks for
+#[derive(Debug)]
+pub struct Options {
+    pub scene: SceneSource,
+    pub output: PathBuf,
+    pub format: Format,
+    pub depth: BitDepth,
+    pub aovs: bool,
+    pub width: Option<Int>,
+    pub height: Option<Int>,
+    pub samples: Option<Int>,
+    pub max_depth: Option<Option<Int>>,
+    pub denoise_iters: Option<Int>,
+    pub seed: Option<u64>,
+    pub position: Option<Vec3>,
+    pub yaw: Option<Float>,
+    pub pitch: Option<Float>,
+    pub target: Option<Vec3>,
+    pub up: Option<Vec3>,
+    pub fov: Option<Float>,
+    pub projection: Option<Projection>,
+    pub aperture: Option<Float>,
+    pub focus_distance: Option<Float>,
+    pub exposure: Option<Float>,
+    pub operator: Option<Operator>,
+    pub white_point: Option<Float>,
+    pub headless: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            scene: SceneSource::Builtin("cornell_basic".to_string()),
+            output: PathBuf::from("../images/dump.ppm"),
+            format: Format::Ppm,
+            depth: BitDepth::Eight,
+            aovs: false,
+            width: None,
+            height: None,
+            samples: None,
+            max_depth: None,
+            denoise_iters: None,
+            seed: None,
+            position: None,
+            yaw: None,
+            pitch: None,
+            target: None,
+            up: None,
+            fov: None,
+            projection: None,
+            aperture: None,
+            focus_distance: None,
+            exposure: None,
+            operator: None,
+            white_point: None,
+            headless: false,
+        }
+    }
+}
+
+impl Options {
+    pub fn parse<I>(args: I) -> Result<Self, CliError>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut options = Options::default();
+        let mut format = None;
+        let mut depth = None;
+        let mut args = args.into_iter();
+
+        while let Some(option) = args.next() {
+            let mut value = || args.next().ok_or_else(|| CliError::MissingValue(option.clone()));
+            match option.as_str() {
+                "-h" | "--help" => return Err(CliError::Help),
+                "--headless" => options.headless = true,
+                "--aovs" => options.aovs = true,
+                "--scene" => {
+                    let name = value()?;
+                    options.scene = if debug_scenes::by_name(&name).is_some() {
+                        SceneSource::Builtin(name)
+                    }
+                    else if Path::new(&name).extension().is_some_and(|extension| extension == "toml") {
+                        SceneSource::File(PathBuf::from(name))
+                    }
+                    else {
+                        return Err(CliError::UnknownScene(name));
+                    };
+                }
+                "-o" | "--output" => options.output = PathBuf::from(value()?),
+                "--format" => {
+                    let name = value()?;
+                    format = Some(Format::from_name(&name).ok_or(CliError::UnknownFormat(name))?);
+                }
+                "--bit-depth" => {
+                    let bits = value()?;
+                    depth = match bits.parse().ok().and_then(BitDepth::from_bits) {
+                        Some(depth) => Some(depth),
+                        None => return Err(CliError::InvalidValue { option, value: bits }),
+                    };
+                }
+                "--width" => options.width = Some(parse_positive(&option, value()?)?),
+                "--height" => options.height = Some(parse_positive(&option, value()?)?),
+                "--spp" => options.samples = Some(parse_positive(&option, value()?)?),
+                "--max-depth" => {
+                    let depth = value()?;
+                    options.max_depth =
+                        Some(if depth == "none" { None } else { Some(parse_value(&option, depth)?) });
+                }
+                "--denoise" => options.denoise_iters = Some(parse_value(&option, value()?)?),
+                "--seed" => options.seed = Some(parse_value(&option, value()?)?),
+                "--position" => options.position = Some(parse_vec3(&option, value()?)?),
+                "--yaw" => options.yaw = Some(parse_value(&option, value()?)?),
+                "--pitch" => options.pitch = Some(parse_value(&option, value()?)?),
+                "--target" => options.target = Some(parse_vec3(&option, value()?)?),
+                "--up" => {
+                    let up = value()?;
+                    let parsed = parse_vec3(&option, up.clone())?;
+                    if parsed.near_zero() {
+                        return Err(CliError::InvalidValue { option, value: up });
+                    }
+                    options.up = Some(parsed.normalized());
+                }
+                "--fov" => options.fov = Some(parse_value(&option, value()?)?),
+                "--projection" => {
+                    let name = value()?;
+                    options.projection = match Projection::from_name(&name) {
+                        Some(projection) => Some(projection),
+                        None => return Err(CliError::InvalidValue { option, value: name }),
+                    };
+                }
+                "--aperture" => options.aperture = Some(parse_value(&option, value()?)?),
+                "--focus-distance" => options.focus_distance = Some(parse_positive(&option, value()?)?),
+                "--exposure" => options.exposure = Some(parse_value(&option, value()?)?),
+                "--tonemap" => {
+                    let name = value()?;
+                    options.operator = match Operator::from_name(&name) {
+                        Some(operator) => Some(operator),
+                        None => return Err(CliError::InvalidValue { option, value: name }),
+                    };
+                }
+                "--white-point" => options.white_point = Some(parse_positive(&option, value()?)?),
+                _ => return Err(CliError::UnknownOption(option)),
+            }
+        }
+
+        // an explicit format wins, otherwise the extension decides and anything unknown is an error
+        // rather than silently writing ppm data into a file called .exr
+        options.format = match format {
+            Some(format) => format,
+            None => match options.output.extension() {
+                Some(extension) => Format::from_path(&options.output)
+                    .ok_or_else(|| CliError::UnknownFormat(extension.to_string_lossy().into_owned()))?,
+                None => Format::Ppm,
+            },
+        };
+
+        options.depth = depth.unwrap_or(options.format.default_depth());
+        if !options.format.supports_depth(options.depth) {
+            return Err(CliError::UnsupportedDepth(options.format, options.depth.bits()));
+        }
+        if options.aovs && options.format != Format::Exr {
+            return Err(CliError::LayersNeedExr);
+        }
+
+        Ok(options)
+    }
+
+    // fails when the target can't be aimed at from wherever the camera ends up, which depends on
+    // the scene so it can't be checked while parsing
+    pub fn apply(&self, camera: &mut Camera) -> Result<(), CliError> {
+        camera.width = self.width.unwrap_or(camera.width);
+        camera.height = self.height.unwrap_or(camera.height);
+        camera.samples = self.samples.unwrap_or(camera.samples);
+        camera.max_depth = self.max_depth.unwrap_or(camera.max_depth);
+        camera.denoise_iters = self.denoise_iters.unwrap_or(camera.denoise_iters);
+        camera.seed = self.seed.or(camera.seed);
+        camera.position = self.position.unwrap_or(camera.position);
+        camera.yaw = self.yaw.unwrap_or(camera.yaw);
+        camera.pitch = self.pitch.unwrap_or(camera.pitch);
+        camera.fov = self.fov.unwrap_or(camera.fov);
+        camera.projection = self.projection.unwrap_or(camera.projection);
+        camera.aperture = self.aperture.unwrap_or(camera.aperture);
+        camera.focus_distance = self.focus_distance.unwrap_or(camera.focus_distance);
+        camera.world_up = self.up.unwrap_or(camera.world_up);
+        if let Some(target) = self.target {
+            if Camera::aim_problem(camera.position, target, camera.world_up).is_some() {
+                let value = format!("{},{},{}", target.x, target.y, target.z);
+                return Err(CliError::InvalidValue { option: "--target".to_string(), value });
+            }
+            camera.aim(camera.position, target, camera.world_up, camera.fov);
+            if self.focus_distance.is_none() {
+                camera.focus_distance = (target - camera.position).length();
+            }
+        }
+        camera.tonemap.exposure = self.exposure.unwrap_or(camera.tonemap.exposure);
+        camera.tonemap.operator = self.operator.unwrap_or(camera.tonemap.operator);
+        camera.tonemap.white_point = self.white_point.unwrap_or(camera.tonemap.white_point);
+
+        Ok(())
+    }
+}
+
+fn parse_value<T: FromStr>(option: &str, value: String) -> Result<T, CliError> {
+    value.parse().map_err(|_| CliError::InvalidValue { option: option.to_string(), value })
+}
+
+fn parse_positive<T: FromStr + PartialOrd + Default>(option: &str, value: String) -> Result<T, CliError> {
+    match value.parse::<T>() {
+        Ok(parsed) if parsed > T::default() => Ok(parsed),
+        _ => Err(CliError::InvalidValue { option: option.to_string(), value }),
+    }
+}
+
+fn parse_vec3(option: &str, value: String) -> Result<Vec3, CliError> {
+    let parts: Vec<Float> = value
+        .split(',')
+        .map(|part| part.trim().parse())
+        .collect::<Result<_, _>>()
+        .map_err(|_| CliError::InvalidValue { option: option.to_string(), value: value.clone() })?;
+    match parts[..] {
+        [x, y, z] => Ok(Vec3::build(x, y, z)),
+        _ => Err(CliError::InvalidValue { option: option.to_string(), value }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use path_tracer::scene_file;
+
+    fn parse(args: &str) -> Result<Options, CliError> {
+        Options::parse(args.split_whitespace().map(String::from))
+    }
+
+    fn applied(args: &str) -> Camera {
+        let mut camera = scene_file::default_camera();
+        parse(args).unwrap().apply(&mut camera).unwrap();
+        camera
+    }
+
+    #[test]
+    fn a_target_is_in_focus_unless_told_otherwise() {
+        assert_eq!(applied("--position 0,2,7 --target 0,2,2").focus_distance, 5.);
+        assert_eq!(applied("--target 0,2,2 --focus-distance 3").focus_distance, 3.);
+        assert_eq!(applied("--position 0,2,9").focus_distance, 7.);
+    }
+
+    fn vector(vector: Option<Vec3>) -> Option<(Float, Float, Float)> {
+        vector.map(|vector| (vector.x, vector.y, vector.z))
+    }
+
+    #[test]
+    fn nothing_given_keeps_the_defaults() {
+        let options = parse("").unwrap();
+        assert!(matches!(&options.scene, SceneSource::Builtin(name) if name == "cornell_basic"));
+        assert_eq!(options.output, PathBuf::from("../images/dump.ppm"));
+        assert_eq!((options.format, options.depth), (Format::Ppm, BitDepth::Eight));
+        assert!(!options.aovs && !options.headless);
+        assert!(options.width.is_none() && options.samples.is_none() && options.max_depth.is_none());
+        assert!(options.position.is_none() && options.target.is_none() && options.operator.is_none());
+    }
+
+    #[test]
+    fn every_option_is_picked_up() {
+        let options = parse(
+            "--scene cornell_room -o out.exr --bit-depth 32 --aovs --width 64 --height 48 --spp 5 \
+             --max-depth 7 --denoise 2 --seed 9 --position 1,2,3 --yaw 0.5 --pitch -0.25 --target 0,1,0 \
+             --up 0,2,0 --fov 40 --projection fisheye --aperture 0.1 --focus-distance 4 --exposure -1 \
+             --tonemap aces-fitted --white-point 8 --headless",
+        )
+        .unwrap();
+        assert!(matches!(&options.scene, SceneSource::Builtin(name) if name == "cornell_room"));
+        assert_eq!(options.output, PathBuf::from("out.exr"));
+        assert_eq!((options.format, options.depth), (Format::Exr, BitDepth::ThirtyTwo));
+        assert!(options.aovs && options.headless);
+        assert_eq!((options.width, options.height, options.samples), (Some(64), Some(48), Some(5)));
+        assert_eq!(
+            (options.max_depth, options.denoise_iters, options.seed),
+            (Some(Some(7)), Some(2), Some(9))
+        );
+        assert_eq!(vector(options.position), Some((1., 2., 3.)));
+        assert_eq!((options.yaw, options.pitch, options.fov), (Some(0.5), Some(-0.25), Some(40.)));
+        assert_eq!(vector(options.target), Some((0., 1., 0.)));
+        assert_eq!(vector(options.up), Some((0., 1., 0.)));
+        assert_eq!(options.projection, Some(Projection::Fisheye));
+        assert_eq!((options.aperture, options.focus_distance), (Some(0.1), Some(4.)));
+        assert_eq!((options.exposure, options.white_point), (Some(-1.), Some(8.)));
+        assert_eq!(options.operator, Some(Operator::AcesFitted));
+
+        assert_eq!(parse("--max-depth none").unwrap().max_depth, Some(None));
+        assert!(matches!(parse("--scene scenes/x.toml").unwrap().scene, SceneSource::File(_)));
+        assert_eq!(parse("-o x.png --format pfm").unwrap().format, Format::Pfm);
+    }
+
+    #[test]
+    fn bad_input_is_rejected() {
+        let invalid = |args: &str, flag: &str| {
+            matches!(parse(args), Err(CliError::InvalidValue { option, .. }) if option == flag)
+        };
+        assert!(invalid("--width 0", "--width"));
+        assert!(invalid("--spp -3", "--spp"));
+        assert!(invalid("--position 1,2", "--position"));
+        assert!(invalid("--up 0,0,0", "--up"));
+        assert!(invalid("--bit-depth 12", "--bit-depth"));
+        assert!(invalid("--tonemap sepia", "--tonemap"));
+        assert!(
+            matches!(parse("-o image.tiff"), Err(CliError::UnknownFormat(extension)) if extension == "tiff")
+        );
+        assert!(matches!(parse("--scene nowhere"), Err(CliError::UnknownScene(_))));
+        assert!(matches!(parse("--width"), Err(CliError::MissingValue(option)) if option == "--width"));
+        assert!(matches!(parse("--colour red"), Err(CliError::UnknownOption(_))));
+        assert!(matches!(parse("-o x.pfm --bit-depth 8"), Err(CliError::UnsupportedDepth(Format::Pfm, 8))));
+        assert!(matches!(parse("-o x.png --aovs"), Err(CliError::LayersNeedExr)));
+        assert!(matches!(parse("--help"), Err(CliError::Help)));
+    }
+
+    #[test]
+    fn degenerate_targets_are_rejected_once_applied() {
+        for args in ["--position 0,2,7 --target 0,2,7", "--position 0,2,7 --target 0,5,7"] {
+            let mut camera = scene_file::default_camera();
+            let applied = parse(args).unwrap().apply(&mut camera);
+            assert!(matches!(applied, Err(CliError::InvalidValue { option, .. }) if option == "--target"));
+        }
+    }
+}
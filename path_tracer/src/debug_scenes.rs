@@ -1,5 +1,3 @@
-#![allow(dead_code)]
-
 use crate::geometry::Sphere;
 use crate::geometry::Triangle;
 use crate::materials::Glass;
@@ -8,8 +6,18 @@ use crate::materials::Material;
 use crate::materials::Metal;
 use crate::materials::Source;
 use crate::scene::Scene;
+use crate::utils::Float;
 use crate::vector::Vec3;
 
+pub fn by_name(name: &str) -> Option<fn(&mut Scene)> {
+    match name {
+        "cornell_room" => Some(cornell_room),
+        "cornell_basic" => Some(cornell_basic),
+        "furnace" => Some(furnace),
+        _ => None,
+    }
+}
+
 // i really need to make an ergonomic scene editor
 // goddamn this sucks
 // it is so much boilerplate tho to make a system better than this
@@ -18,7 +26,7 @@ pub fn cornell_room(scene: &mut Scene) {
     let lambertian_green = Material::Lambertian(Lambertian::build(Vec3::build(0.12, 0.45, 0.15)));
     let lambertian_white = Material::Lambertian(Lambertian::build(Vec3::build(0.73, 0.73, 0.73)));
     let source = Material::Source(Source::build(Vec3::build(15., 13., 5.)));
-    let mirror = Material::Metal(Metal::build(Vec3::build(0.82, 0.95, 0.93), 0.005));
+    let mirror = Material::Metal(Metal::build(Vec3::build(0.82, 0.95, 0.93), 0.07));
     let ideal_mirror = Material::Metal(Metal::build(Vec3::build(1., 1., 1.), 0.));
     let glass = Material::Glass(Glass::build(Vec3::build(1., 1., 1.), 1.5));
     let blue_glass = Material::Glass(Glass::build(Vec3::build(0.95, 0.95, 1.), 1.9));
@@ -393,3 +401,27 @@ pub fn cornell_basic(scene: &mut Scene) {
     scene.add_triangle(Triangle::build(q1, q2, q6, white));
     scene.add_triangle(Triangle::build(q1, q6, q5, white));
 }
+
+// white furnace test, a uniform white background and no lights, so anything that neither absorbs
+// nor loses energy disappears into it, top row is glass getting rougher from left to right and the
+// bottom row a white diffuse sphere followed by metals doing the same, the smooth and slightly rough
+// spheres should vanish while the roughest ones darken since single scattering microfacets drop the
+// light that bounces between them, roughly 1%, 20% and 65% at roughness 0.3, 0.6 and 1
+pub fn furnace(scene: &mut Scene) {
+    scene.set_background(Vec3::build(1., 1., 1.));
+    let white = Vec3::build(1., 1., 1.);
+    let roughnesses = [0., 0.1, 0.3, 0.6, 1.];
+
+    for (index, roughness) in roughnesses.into_iter().enumerate() {
+        let x = (index as Float - 2.) * 1.4;
+        let glass = Material::Glass(Glass::build(white, 1.5).with_roughness(roughness));
+        scene.add_sphere(Sphere::build(Vec3::build(x, 2.7, 0.), 0.6, glass));
+        let bottom = if index == 0 {
+            Material::Lambertian(Lambertian::build(white))
+        }
+        else {
+            Material::Metal(Metal::build(white, roughness))
+        };
+        scene.add_sphere(Sphere::build(Vec3::build(x, 1.3, 0.), 0.6, bottom));
+    }
+}
@@ -1,14 +1,148 @@
+use crate::bvh::Bvh;
 use crate::materials::Material;
+use crate::obj::ObjModel;
+use crate::ray_hit::Bounded;
 use crate::ray_hit::HitRecord;
 use crate::ray_hit::Hittable;
 use crate::ray_hit::Ray;
+use crate::utils::random;
 use crate::utils::Float;
 use crate::utils::Interval;
+use crate::utils::INFIN;
+use crate::utils::PI;
+use crate::utils::SMALL;
+use crate::utils::SQRT_2;
 use crate::vector::Vec3;
 
+#[derive(Clone, Copy, Debug)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub const fn build(min: Vec3, max: Vec3) -> Self {
+        Aabb { min, max }
+    }
+
+    pub fn empty() -> Self {
+        Aabb::build(Vec3::build(INFIN, INFIN, INFIN), Vec3::build(-INFIN, -INFIN, -INFIN))
+    }
+
+    pub fn from_points(points: &[Vec3]) -> Self {
+        points.iter().fold(Aabb::empty(), |bounds, point| bounds.grow(point))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    pub fn grow(&self, point: &Vec3) -> Self {
+        Aabb::build(self.min.component_min(point), self.max.component_max(point))
+    }
+
+    pub fn union(&self, other: &Aabb) -> Self {
+        Aabb::build(self.min.component_min(&other.min), self.max.component_max(&other.max))
+    }
+
+    pub fn contains(&self, point: &Vec3) -> bool {
+        (0..3).all(|axis| self.min.axis(axis) <= point.axis(axis) && point.axis(axis) <= self.max.axis(axis))
+    }
+
+    pub fn centroid(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn diagonal(&self) -> Vec3 {
+        self.max - self.min
+    }
+
+    pub fn surface_area(&self) -> Float {
+        if self.is_empty() {
+            return 0.;
+        }
+        let extent = self.diagonal();
+        2. * (extent.x * extent.y + extent.y * extent.z + extent.z * extent.x)
+    }
+
+    pub fn longest_axis(&self) -> usize {
+        let extent = self.diagonal();
+        if extent.x > extent.y && extent.x > extent.z {
+            0
+        }
+        else if extent.y > extent.z {
+            1
+        }
+        else {
+            2
+        }
+    }
+
+    // slab test, gives back the part of the interval spent inside the box
+    // nan from 0 * inf falls through because Float::max and Float::min ignore it
+    pub fn hit(&self, ray: &Ray, inverse_direction: &Vec3, interval: &Interval) -> Option<Interval> {
+        let mut t_min = interval.min;
+        let mut t_max = interval.max;
+        for axis in 0..3 {
+            let origin = ray.origin.axis(axis);
+            let inverse = inverse_direction.axis(axis);
+            let mut t_near = (self.min.axis(axis) - origin) * inverse;
+            let mut t_far = (self.max.axis(axis) - origin) * inverse;
+            if inverse < 0. {
+                std::mem::swap(&mut t_near, &mut t_far);
+            }
+
+            t_min = t_near.max(t_min);
+            t_max = t_far.min(t_max);
+            if t_max < t_min {
+                return None;
+            }
+        }
+
+        Some(Interval::build(t_min, t_max))
+    }
+}
+
 pub enum Geometry {
     Sphere(Sphere),
     Triangle(Triangle),
+    Mesh(Mesh),
+}
+
+// point picked on an emitter as seen from some origin, pdf is over solid angle at that origin
+pub struct LightSample {
+    pub point: Vec3,
+    pub pdf: Float,
+}
+
+impl Geometry {
+    // face is only looked at for meshes
+    pub fn sample_toward(&self, face: usize, origin: &Vec3) -> Option<LightSample> {
+        match self {
+            Geometry::Sphere(sphere) => sphere.sample_toward(origin),
+            Geometry::Triangle(triangle) => triangle.sample_toward(origin),
+            Geometry::Mesh(mesh) => mesh.sample_face_toward(face, origin),
+        }
+    }
+
+    // the pdf sample_toward would have had for landing on point
+    pub fn pdf_toward(&self, face: usize, origin: &Vec3, point: &Vec3) -> Float {
+        match self {
+            Geometry::Sphere(sphere) => sphere.pdf_toward(origin, point),
+            Geometry::Triangle(triangle) => triangle.pdf_toward(origin, point),
+            Geometry::Mesh(mesh) => mesh.pdf_face_toward(face, origin, point),
+        }
+    }
+}
+
+impl Bounded for Geometry {
+    fn bounding_box(&self) -> Aabb {
+        match self {
+            Geometry::Sphere(sphere) => sphere.bounding_box(),
+            Geometry::Triangle(triangle) => triangle.bounding_box(),
+            Geometry::Mesh(mesh) => mesh.bounding_box(),
+        }
+    }
 }
 
 impl Hittable for Geometry {
@@ -16,6 +150,7 @@ impl Hittable for Geometry {
         match self {
             Geometry::Sphere(sphere) => sphere.hit(ray, record),
             Geometry::Triangle(triangle) => triangle.hit(ray, record),
+            Geometry::Mesh(mesh) => mesh.hit(ray, record),
         }
     }
 }
@@ -34,6 +169,76 @@ impl Sphere {
     fn get_normal(&self, at: &Vec3) -> Vec3 {
         (*at - self.center) / self.radius
     }
+
+    // longitude around y for u starting at -x, latitude from the bottom pole for v
+    fn get_uv(normal: &Vec3) -> (Float, Float) {
+        let theta = (-normal.y).clamp(-1., 1.).acos();
+        let phi = (-normal.z).atan2(normal.x) + PI;
+        (phi / (2. * PI), theta / PI)
+    }
+
+    // dp/du and dp/dv for get_uv's mapping, u goes nowhere at the poles so any tangent does there
+    fn get_tangents(&self, normal: &Vec3) -> (Vec3, Vec3) {
+        let around = Vec3::build(normal.z, 0., -normal.x);
+        let direction = if around.near_zero() { normal.orthonormal_basis().0 } else { around.normalized() };
+        (around * (2. * PI * self.radius), normal.cross_product(&direction) * (PI * self.radius))
+    }
+
+    // samples the cone of directions the sphere covers, which never wastes a sample on the far side
+    pub fn sample_toward(&self, origin: &Vec3) -> Option<LightSample> {
+        let to_center = self.center - *origin;
+        let distance_squared = to_center.inner_product(&to_center);
+        let radius_squared = self.radius * self.radius;
+        if distance_squared <= radius_squared {
+            return self.sample_area_toward(origin);
+        }
+
+        let cos_max = (1. - radius_squared / distance_squared).max(0.).sqrt();
+        let cos_theta = 1. - random() * (1. - cos_max);
+        let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+        let phi = 2. * PI * random();
+
+        let axis = to_center.normalized();
+        let (tangent, bitangent) = axis.orthonormal_basis();
+        let direction =
+            tangent * (sin_theta * phi.cos()) + bitangent * (sin_theta * phi.sin()) + axis * cos_theta;
+
+        let along = to_center.inner_product(&direction);
+        let time = along - (along * along - distance_squared + radius_squared).max(0.).sqrt();
+        let point = *origin + direction * time;
+
+        Some(LightSample { point, pdf: 1. / (2. * PI * (1. - cos_max)) })
+    }
+
+    // only for origins inside the sphere where the cone covers everything
+    fn sample_area_toward(&self, origin: &Vec3) -> Option<LightSample> {
+        let normal = Vec3::random_unit_vector();
+        let point = self.center + normal * self.radius;
+        solid_angle_sample(origin, point, normal, self.area())
+    }
+
+    pub fn pdf_toward(&self, origin: &Vec3, point: &Vec3) -> Float {
+        let to_center = self.center - *origin;
+        let distance_squared = to_center.inner_product(&to_center);
+        let radius_squared = self.radius * self.radius;
+        if distance_squared <= radius_squared {
+            return solid_angle_pdf(origin, point, &self.get_normal(point), self.area());
+        }
+
+        let cos_max = (1. - radius_squared / distance_squared).max(0.).sqrt();
+        1. / (2. * PI * (1. - cos_max))
+    }
+
+    fn area(&self) -> Float {
+        4. * PI * self.radius * self.radius
+    }
+}
+
+impl Bounded for Sphere {
+    fn bounding_box(&self) -> Aabb {
+        let extent = Vec3::build(self.radius, self.radius, self.radius);
+        Aabb::build(self.center - extent, self.center + extent)
+    }
 }
 
 impl Hittable for Sphere {
@@ -59,10 +264,15 @@ impl Hittable for Sphere {
 
         record.point = ray.at_time(root);
         record.ray_in = ray.direction;
-        record.set_face_normal(&self.get_normal(&record.point));
+        let normal = self.get_normal(&record.point);
+        record.set_face_normal(&normal);
+        record.uv = Sphere::get_uv(&normal);
+        record.uv_density = 1. / (PI * self.radius * SQRT_2);
+        (record.tangent, record.bitangent) = self.get_tangents(&normal);
         record.intersection_time = root;
         record.interval.max = record.intersection_time;
         record.material = self.material;
+        record.face = 0;
 
         true
     }
@@ -72,55 +282,326 @@ pub struct Triangle {
     a: Vec3,
     b: Vec3,
     c: Vec3,
+    normals: Option<[Vec3; 3]>,
+    uvs: Option<[(Float, Float); 3]>,
     material: Material,
 }
 
 impl Triangle {
     pub const fn build(a: Vec3, b: Vec3, c: Vec3, material: Material) -> Self {
-        Triangle { a, b, c, material }
+        Triangle { a, b, c, normals: None, uvs: None, material }
+    }
+
+    // vertex normals in the same order as the vertices, they get interpolated for shading only
+    pub const fn build_smooth(a: Vec3, b: Vec3, c: Vec3, normals: [Vec3; 3], material: Material) -> Self {
+        Triangle { a, b, c, normals: Some(normals), uvs: None, material }
+    }
+
+    // texture coordinates per vertex, in the same order as the vertices
+    pub const fn with_uvs(mut self, uvs: [(Float, Float); 3]) -> Self {
+        self.uvs = Some(uvs);
+        self
+    }
+
+    pub fn material(&self) -> Material {
+        self.material
+    }
+
+    pub fn sample_toward(&self, origin: &Vec3) -> Option<LightSample> {
+        sample_triangle_toward(&self.a, &self.b, &self.c, origin)
+    }
+
+    pub fn pdf_toward(&self, origin: &Vec3, point: &Vec3) -> Float {
+        triangle_pdf_toward(&self.a, &self.b, &self.c, origin, point)
+    }
+}
+
+impl Bounded for Triangle {
+    // axis aligned triangles would get a flat box, so pad it out to keep the slab test well behaved
+    fn bounding_box(&self) -> Aabb {
+        let bounds = Aabb::from_points(&[self.a, self.b, self.c]);
+        let padding = Vec3::build(SMALL, SMALL, SMALL);
+        Aabb::build(bounds.min - padding, bounds.max + padding)
     }
 }
 
 impl Hittable for Triangle {
     fn hit(&self, ray: &Ray, record: &mut HitRecord) -> bool {
-        let edge1 = self.b - self.a;
-        let edge2 = self.c - self.a;
+        let Some(hit) = moller_trumbore(&self.a, &self.b, &self.c, ray, &record.interval)
+        else {
+            return false;
+        };
 
-        let pvector = ray.direction.cross_product(&edge2);
-        let determinant = edge1.inner_product(&pvector);
+        fill_triangle_hit(record, ray, hit, [self.a, self.b, self.c], self.normals, self.uvs);
+        record.material = self.material;
+        record.face = 0;
 
-        if Interval::near_zero().contains(determinant) {
-            return false;
-        }
+        true
+    }
+}
 
-        let tvector = ray.origin - self.a;
-        let u = tvector.inner_product(&pvector) / determinant;
-        if !Interval::build(0., 1.).contains(u) {
-            return false;
-        }
+// everything about a triangle hit but the material and face, shared between lone triangles and
+// mesh faces, hit is what moller_trumbore found
+fn fill_triangle_hit(
+    record: &mut HitRecord,
+    ray: &Ray,
+    hit: (Float, Float, Float),
+    [a, b, c]: [Vec3; 3],
+    normals: Option<[Vec3; 3]>,
+    uvs: Option<[(Float, Float); 3]>,
+) {
+    let (time, u, v) = hit;
+    let normal = (b - a).cross_product(&(c - a)).normalized();
+    record.point = ray.at_time(time);
+    record.ray_in = ray.direction;
+    record.set_face_normal(&normal);
+    if let Some(normals) = &normals {
+        record.set_shading_normal(&interpolate_normal(normals, u, v));
+    }
+    record.uv = uvs.map_or((u, v), |uvs| interpolate_uv(&uvs, u, v));
+    record.uv_density = uv_density(&a, &b, &c, uvs);
+    (record.tangent, record.bitangent) = uv_tangents(&a, &b, &c, uvs);
+    record.intersection_time = time;
+    record.interval.max = record.intersection_time;
+}
 
-        let qvector = tvector.cross_product(&edge1);
-        let v = ray.direction.inner_product(&qvector) / determinant;
-        if !Interval::build(0., 1.).contains(v) {
-            return false;
-        }
+// gives back the hit time and the barycentric u, v weighting b and c
+fn moller_trumbore(
+    a: &Vec3,
+    b: &Vec3,
+    c: &Vec3,
+    ray: &Ray,
+    interval: &Interval,
+) -> Option<(Float, Float, Float)> {
+    let edge1 = *b - *a;
+    let edge2 = *c - *a;
 
-        if u + v > 1. {
-            return false;
+    let pvector = ray.direction.cross_product(&edge2);
+    let determinant = edge1.inner_product(&pvector);
+
+    if Interval::near_zero().contains(determinant) {
+        return None;
+    }
+
+    let tvector = ray.origin - *a;
+    let u = tvector.inner_product(&pvector) / determinant;
+    if !Interval::build(0., 1.).contains(u) {
+        return None;
+    }
+
+    let qvector = tvector.cross_product(&edge1);
+    let v = ray.direction.inner_product(&qvector) / determinant;
+    if !Interval::build(0., 1.).contains(v) {
+        return None;
+    }
+
+    if u + v > 1. {
+        return None;
+    }
+
+    let time = edge2.inner_product(&qvector) / determinant;
+    if !interval.contains(time) {
+        return None;
+    }
+
+    Some((time, u, v))
+}
+
+// uniform over the area, then converted to solid angle
+fn sample_triangle_toward(a: &Vec3, b: &Vec3, c: &Vec3, origin: &Vec3) -> Option<LightSample> {
+    let root = random().sqrt();
+    let (weight_b, weight_c) = (random() * root, 1. - root);
+    let point = *a + (*b - *a) * weight_b + (*c - *a) * weight_c;
+    let cross = (*b - *a).cross_product(&(*c - *a));
+    let area = 0.5 * cross.length();
+    solid_angle_sample(origin, point, cross.normalized(), area)
+}
+
+fn triangle_pdf_toward(a: &Vec3, b: &Vec3, c: &Vec3, origin: &Vec3, point: &Vec3) -> Float {
+    let cross = (*b - *a).cross_product(&(*c - *a));
+    solid_angle_pdf(origin, point, &cross.normalized(), 0.5 * cross.length())
+}
+
+fn solid_angle_sample(origin: &Vec3, point: Vec3, normal: Vec3, area: Float) -> Option<LightSample> {
+    let pdf = solid_angle_pdf(origin, &point, &normal, area);
+    if pdf <= 0. {
+        return None;
+    }
+
+    Some(LightSample { point, pdf })
+}
+
+// area density 1 / area converted to solid angle at origin, zero for grazing or degenerate cases
+fn solid_angle_pdf(origin: &Vec3, point: &Vec3, normal: &Vec3, area: Float) -> Float {
+    let to_point = *point - *origin;
+    let distance_squared = to_point.inner_product(&to_point);
+    let cos_light = (to_point.inner_product(normal) / distance_squared.sqrt()).abs();
+    if cos_light < SMALL || area < SMALL * SMALL {
+        return 0.;
+    }
+
+    distance_squared / (cos_light * area)
+}
+
+fn interpolate_normal(normals: &[Vec3; 3], u: Float, v: Float) -> Vec3 {
+    (normals[0] * (1. - u - v) + normals[1] * u + normals[2] * v).normalized()
+}
+
+// square root of uv area over surface area, without uvs the barycentrics cover half the uv square
+fn uv_density(a: &Vec3, b: &Vec3, c: &Vec3, uvs: Option<[(Float, Float); 3]>) -> Float {
+    let area = (*b - *a).cross_product(&(*c - *a)).length();
+    let uv_area = match uvs {
+        Some([uv_a, uv_b, uv_c]) => {
+            ((uv_b.0 - uv_a.0) * (uv_c.1 - uv_a.1) - (uv_c.0 - uv_a.0) * (uv_b.1 - uv_a.1)).abs()
         }
+        None => 1.,
+    };
+    (uv_area / area.max(SMALL * SMALL)).sqrt()
+}
+
+// dp/du and dp/dv from how the uvs stretch over the triangle, the barycentric fallback has a at the
+// origin with b and c one unit along u and v
+fn uv_tangents(a: &Vec3, b: &Vec3, c: &Vec3, uvs: Option<[(Float, Float); 3]>) -> (Vec3, Vec3) {
+    let [uv_a, uv_b, uv_c] = uvs.unwrap_or([(0., 0.), (1., 0.), (0., 1.)]);
+    let (edge1, edge2) = (*b - *a, *c - *a);
+    let (du1, dv1) = (uv_b.0 - uv_a.0, uv_b.1 - uv_a.1);
+    let (du2, dv2) = (uv_c.0 - uv_a.0, uv_c.1 - uv_a.1);
+    let determinant = du1 * dv2 - du2 * dv1;
+    if determinant.abs() < SMALL * SMALL {
+        return edge1.cross_product(&edge2).normalized().orthonormal_basis();
+    }
+
+    ((edge1 * dv2 - edge2 * dv1) / determinant, (edge2 * du1 - edge1 * du2) / determinant)
+}
+
+fn interpolate_uv(uvs: &[(Float, Float); 3], u: Float, v: Float) -> (Float, Float) {
+    let w = 1. - u - v;
+    (uvs[0].0 * w + uvs[1].0 * u + uvs[2].0 * v, uvs[0].1 * w + uvs[1].1 * u + uvs[2].1 * v)
+}
+
+#[derive(Clone, Copy)]
+pub struct MeshFace {
+    pub positions: [u32; 3],
+    pub normals: Option<[u32; 3]>,
+    pub uvs: Option<[u32; 3]>,
+    pub material: u32,
+}
+
+// vertex data and materials are shared between faces, and the faces get their own bvh so the whole
+// mesh sits in the scene bvh as a single entry
+pub struct Mesh {
+    positions: Vec<Vec3>,
+    normals: Vec<Vec3>,
+    uvs: Vec<(Float, Float)>,
+    faces: Vec<MeshFace>,
+    materials: Vec<Material>,
+    bvh: Bvh,
+}
+
+impl Mesh {
+    pub fn build(
+        positions: Vec<Vec3>,
+        normals: Vec<Vec3>,
+        uvs: Vec<(Float, Float)>,
+        faces: Vec<MeshFace>,
+        materials: Vec<Material>,
+    ) -> Self {
+        let bounds: Vec<Aabb> = faces
+            .iter()
+            .map(|face| {
+                let bounds = Aabb::from_points(&face.positions.map(|index| positions[index as usize]));
+                let padding = Vec3::build(SMALL, SMALL, SMALL);
+                Aabb::build(bounds.min - padding, bounds.max + padding)
+            })
+            .collect();
+        let bvh = Bvh::build(&bounds);
+
+        Mesh { positions, normals, uvs, faces, materials, bvh }
+    }
+
+    // faces without a usemtl get the fallback material, which goes on the end of the material list
+    pub fn from_obj(model: &ObjModel, fallback: Material) -> Self {
+        let mut materials = model.materials.clone();
+        let fallback_index = materials.len() as u32;
+        materials.push(fallback);
+
+        let faces = model
+            .faces
+            .iter()
+            .map(|face| {
+                let vertices = face.vertices;
+                let normals = vertices.map(|vertex| vertex.normal);
+                let uvs = vertices.map(|vertex| vertex.uv);
+                MeshFace {
+                    positions: vertices.map(|vertex| vertex.position as u32),
+                    normals: all_indices(normals),
+                    uvs: all_indices(uvs),
+                    material: face.material.map_or(fallback_index, |index| index as u32),
+                }
+            })
+            .collect();
+
+        Mesh::build(model.positions.clone(), model.normals.clone(), model.uvs.clone(), faces, materials)
+    }
+
+    pub fn face_count(&self) -> usize {
+        self.faces.len()
+    }
+
+    pub fn face_material(&self, face: usize) -> Material {
+        self.materials[self.faces[face].material as usize]
+    }
+
+    pub fn sample_face_toward(&self, face: usize, origin: &Vec3) -> Option<LightSample> {
+        let [a, b, c] = self.face_positions(&self.faces[face]);
+        sample_triangle_toward(&a, &b, &c, origin)
+    }
 
-        let time = edge2.inner_product(&qvector) / determinant;
-        if !record.interval.contains(time) {
+    pub fn pdf_face_toward(&self, face: usize, origin: &Vec3, point: &Vec3) -> Float {
+        let [a, b, c] = self.face_positions(&self.faces[face]);
+        triangle_pdf_toward(&a, &b, &c, origin, point)
+    }
+
+    fn face_positions(&self, face: &MeshFace) -> [Vec3; 3] {
+        face.positions.map(|index| self.positions[index as usize])
+    }
+
+    fn hit_face(&self, index: usize, ray: &Ray, record: &mut HitRecord) -> bool {
+        let face = &self.faces[index];
+        let [a, b, c] = self.face_positions(face);
+        let Some(hit) = moller_trumbore(&a, &b, &c, ray, &record.interval)
+        else {
             return false;
-        }
+        };
 
-        record.point = ray.at_time(time);
-        record.ray_in = ray.direction;
-        record.set_face_normal(&edge1.cross_product(&edge2).normalized());
-        record.intersection_time = time;
-        record.interval.max = record.intersection_time;
-        record.material = self.material;
+        let normals = face.normals.map(|indices| indices.map(|index| self.normals[index as usize]));
+        let uvs = face.uvs.map(|indices| indices.map(|index| self.uvs[index as usize]));
+        fill_triangle_hit(record, ray, hit, [a, b, c], normals, uvs);
+        record.material = self.materials[face.material as usize];
+        record.face = index;
 
         true
     }
 }
+
+// a face only gets per-vertex attributes when every one of its vertices has them
+fn all_indices(indices: [Option<usize>; 3]) -> Option<[u32; 3]> {
+    match indices {
+        [Some(a), Some(b), Some(c)] => Some([a as u32, b as u32, c as u32]),
+        _ => None,
+    }
+}
+
+impl Bounded for Mesh {
+    fn bounding_box(&self) -> Aabb {
+        let bounds = Aabb::from_points(&self.positions);
+        let padding = Vec3::build(SMALL, SMALL, SMALL);
+        Aabb::build(bounds.min - padding, bounds.max + padding)
+    }
+}
+
+impl Hittable for Mesh {
+    fn hit(&self, ray: &Ray, record: &mut HitRecord) -> bool {
+        self.bvh.hit(ray, record, |index, record| self.hit_face(index, ray, record))
+    }
+}
@@ -0,0 +1,182 @@
+use crate::tonemap::Tonemap;
+use crate::utils::packed_color;
+use crate::utils::Float;
+use crate::utils::Int;
+use crate::utils::SMALL;
+use crate::vector::Vec3;
+
+// running sum of linear radiance, only divided by the count when read so more samples can
+// always be added on top
+#[derive(Clone, Copy, Default)]
+pub struct HdrPixel {
+    pub sum: Vec3,
+    pub samples: u32,
+}
+
+impl HdrPixel {
+    pub fn add(&mut self, sum: Vec3, samples: u32) {
+        self.sum += sum;
+        self.samples += samples;
+    }
+
+    pub fn color(&self) -> Vec3 {
+        if self.samples == 0 {
+            return Vec3::zeros();
+        }
+        self.sum / self.samples as Float
+    }
+}
+
+// everything stays linear and unclamped here, tonemapping and quantizing only happen when a frame
+// is presented or written to an ldr file
+#[derive(Clone)]
+pub struct HdrBuffer {
+    pub pixels: Vec<HdrPixel>,
+    pub height: usize,
+    pub width: usize,
+}
+
+impl HdrBuffer {
+    pub fn build(height: usize, width: usize) -> HdrBuffer {
+        HdrBuffer { height, width, pixels: vec![HdrPixel::default(); width * height] }
+    }
+
+    pub fn clear(&mut self) {
+        self.pixels.fill(HdrPixel::default());
+    }
+
+    pub fn color(&self, x: usize, y: usize) -> Vec3 {
+        self.pixels[y * self.width + x].color()
+    }
+
+    pub fn write_packed(&self, frame: &mut [u32], tonemap: &Tonemap) {
+        for (packed, pixel) in frame.iter_mut().zip(&self.pixels) {
+            *packed = packed_color(tonemap.display(pixel.color()));
+        }
+    }
+
+    // filters the per pixel means and scales them back up by each pixel's count, so the sample
+    // counts stay meaningful afterwards
+    pub fn bilateral_denoise(&mut self, iters: Int) {
+        let radius = 3;
+        // the filter only touches pixels with a full window around them, which tiny images don't have
+        if self.width < 2 * radius + 1 || self.height < 2 * radius + 1 {
+            return;
+        }
+        let mut sigma_spatial = 2.0;
+        let mut sigma_color = 0.1;
+        let mut colors: Vec<Vec3> = self.pixels.iter().map(|pixel| pixel.color()).collect();
+
+        (0..iters).for_each(|_| {
+            let guidance = box_blur(&colors, self.width, self.height, radius);
+            let mut result = colors.clone();
+
+            for y in radius..self.height - radius {
+                for x in radius..self.width - radius {
+                    let center_idx = y * self.width + x;
+
+                    let center_color = guidance[center_idx];
+                    let mut color = Vec3::zeros();
+                    let mut weight_sum = 0.;
+
+                    for dy in -(radius as isize)..=radius as isize {
+                        for dx in -(radius as isize)..=radius as isize {
+                            let ny = (y as isize + dy) as usize;
+                            let nx = (x as isize + dx) as usize;
+                            let idx = ny * self.width + nx;
+
+                            let neighbor_color_input = colors[idx];
+                            if neighbor_color_input.near_zero() {
+                                continue;
+                            }
+                            let neighbor_color_guidance = guidance[idx];
+
+                            let spatial_dist = (dx * dx + dy * dy) as Float;
+                            let color_dist = (neighbor_color_guidance - center_color)
+                                .inner_product(&(neighbor_color_guidance - center_color));
+
+                            let spatial_weight = (-spatial_dist / (2. * sigma_spatial * sigma_spatial)).exp();
+                            let color_weight = (-color_dist / (2. * sigma_color * sigma_color)).exp();
+                            let weight = spatial_weight * color_weight;
+
+                            color += neighbor_color_input * weight;
+                            weight_sum += weight;
+                        }
+                    }
+                    result[center_idx] = color / weight_sum.max(SMALL);
+                }
+            }
+
+            colors = result;
+            sigma_color /= 1.2;
+            sigma_spatial *= 1.2;
+        });
+
+        for (pixel, color) in self.pixels.iter_mut().zip(colors) {
+            pixel.sum = color * pixel.samples as Float;
+        }
+    }
+}
+
+fn box_blur(colors: &[Vec3], width: usize, height: usize, radius: usize) -> Vec<Vec3> {
+    let mut result = colors.to_vec();
+    for y in radius..height.saturating_sub(radius) {
+        for x in radius..width.saturating_sub(radius) {
+            let mut sum = Vec3::zeros();
+            let mut count = 0.;
+            for dy in -(radius as isize)..=(radius as isize) {
+                for dx in -(radius as isize)..=(radius as isize) {
+                    let ny = (y as isize + dy) as usize;
+                    let nx = (x as isize + dx) as usize;
+                    sum += colors[ny * width + nx];
+                    count += 1.;
+                }
+            }
+
+            result[y * width + x] = sum / count;
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn colors(buffer: &HdrBuffer) -> Vec<(Float, Float, Float)> {
+        buffer.pixels.iter().map(|pixel| pixel.color()).map(|color| (color.x, color.y, color.z)).collect()
+    }
+
+    fn filled(width: usize, height: usize) -> HdrBuffer {
+        let mut buffer = HdrBuffer::build(height, width);
+        for (index, pixel) in buffer.pixels.iter_mut().enumerate() {
+            pixel.add(Vec3::build(index as Float, 1., 0.5), 1);
+        }
+        buffer
+    }
+
+    #[test]
+    fn denoise_leaves_images_smaller_than_the_window_alone() {
+        for (width, height) in [(8, 2), (2, 8), (1, 1), (6, 6), (7, 6)] {
+            let mut buffer = filled(width, height);
+            let before = colors(&buffer);
+            buffer.bilateral_denoise(3);
+            assert_eq!(before, colors(&buffer), "{}x{}", width, height);
+        }
+    }
+
+    #[test]
+    fn denoise_runs_once_the_window_fits() {
+        let mut buffer = filled(7, 7);
+        buffer.bilateral_denoise(1);
+        assert!(buffer.pixels.iter().all(|pixel| pixel.color().x.is_finite()));
+    }
+
+    #[test]
+    fn box_blur_handles_images_smaller_than_the_window() {
+        let colors = vec![Vec3::build(1., 2., 3.); 4];
+        let blurred = box_blur(&colors, 2, 2, 3);
+        assert!(blurred.iter().all(|color| (color.x, color.y, color.z) == (1., 2., 3.)));
+    }
+}
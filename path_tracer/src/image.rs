@@ -0,0 +1,299 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+
+use png::BitDepth;
+use png::ColorType;
+use png::Decoder;
+use png::DecodingError;
+use png::Transformations;
+
+use crate::tonemap::srgb_decode;
+use crate::utils::Float;
+use crate::vector::Vec3;
+
+#[derive(Debug)]
+pub enum ImageErrorKind {
+    Io(io::Error),
+    Png(Box<DecodingError>),
+    Ppm(&'static str),
+    UnknownFormat,
+}
+
+#[derive(Debug)]
+pub struct ImageError {
+    pub path: PathBuf,
+    pub kind: ImageErrorKind,
+}
+
+impl ImageError {
+    fn build(path: &Path, kind: ImageErrorKind) -> Self {
+        ImageError { path: path.to_path_buf(), kind }
+    }
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: ", self.path.display())?;
+        match &self.kind {
+            ImageErrorKind::Io(error) => write!(f, "io failure: {}", error),
+            ImageErrorKind::Png(error) => write!(f, "bad png: {}", error),
+            ImageErrorKind::Ppm(problem) => write!(f, "bad ppm: {}", problem),
+            ImageErrorKind::UnknownFormat => write!(f, "not a png or ppm image"),
+        }
+    }
+}
+
+impl std::error::Error for ImageError {}
+
+// linear rgb, rows top to bottom
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Vec3>,
+}
+
+impl Image {
+    pub fn build(width: usize, height: usize, pixels: Vec<Vec3>) -> Self {
+        assert_eq!(pixels.len(), width * height, "image needs exactly width * height pixels");
+        Image { width, height, pixels }
+    }
+
+    // png or ppm picked by the file's magic bytes, linear skips the srgb decode for data like
+    // normal maps that was never meant to be displayed
+    pub fn load(path: &Path, linear: bool) -> Result<Self, ImageError> {
+        let error = |kind| ImageError::build(path, kind);
+        let bytes = fs::read(path).map_err(|io_error| error(ImageErrorKind::Io(io_error)))?;
+        let (width, height, max_value, samples) = if bytes.starts_with(b"\x89PNG") {
+            decode_png(&bytes).map_err(|png_error| error(ImageErrorKind::Png(Box::new(png_error))))?
+        }
+        else if bytes.starts_with(b"P3") || bytes.starts_with(b"P6") {
+            decode_ppm(&bytes).map_err(|problem| error(ImageErrorKind::Ppm(problem)))?
+        }
+        else {
+            return Err(error(ImageErrorKind::UnknownFormat));
+        };
+
+        // 8 bit values go through the srgb curve once each instead of once per pixel
+        let convert = |value: u32| to_linear(value as Float / max_value as Float, linear);
+        let table: Option<Vec<Float>> = (max_value <= 255).then(|| (0..=max_value).map(convert).collect());
+        let channel = |value: u16| match &table {
+            Some(table) => table[value as usize],
+            None => convert(value as u32),
+        };
+        let pixels = samples
+            .chunks_exact(3)
+            .map(|rgb| Vec3::build(channel(rgb[0]), channel(rgb[1]), channel(rgb[2])))
+            .collect();
+
+        Ok(Image::build(width, height, pixels))
+    }
+
+    pub fn texel(&self, x: usize, y: usize) -> Vec3 {
+        self.pixels[y * self.width + x]
+    }
+
+    // box filters 2x2 blocks, an odd last row or column just gets averaged with itself
+    fn downsample(&self) -> Image {
+        let (width, height) = ((self.width / 2).max(1), (self.height / 2).max(1));
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let (left, top) = (2 * x, 2 * y);
+                let (right, bottom) = ((left + 1).min(self.width - 1), (top + 1).min(self.height - 1));
+                let sum = self.texel(left, top)
+                    + self.texel(right, top)
+                    + self.texel(left, bottom)
+                    + self.texel(right, bottom);
+                pixels.push(sum * 0.25);
+            }
+        }
+
+        Image::build(width, height, pixels)
+    }
+}
+
+fn to_linear(value: Float, linear: bool) -> Float {
+    if linear {
+        value
+    }
+    else {
+        srgb_decode(value)
+    }
+}
+
+// the full resolution image followed by every halving down to a single pixel
+pub struct MipMap {
+    pub levels: Vec<Image>,
+}
+
+impl MipMap {
+    pub fn build(image: Image) -> Self {
+        let mut levels = vec![image];
+        while let Some(last) = levels.last().filter(|last| last.width > 1 || last.height > 1) {
+            let next = last.downsample();
+            levels.push(next);
+        }
+
+        MipMap { levels }
+    }
+
+    pub fn base(&self) -> &Image {
+        &self.levels[0]
+    }
+}
+
+// the images a scene's textures use, materials are copied into every hit record so textures hold
+// on to an index into here rather than the image
+#[derive(Default)]
+pub struct ImageTable {
+    maps: Vec<MipMap>,
+    loaded: HashMap<(PathBuf, bool), usize>,
+}
+
+impl ImageTable {
+    pub fn new() -> Self {
+        ImageTable::default()
+    }
+
+    pub fn add(&mut self, map: MipMap) -> usize {
+        self.maps.push(map);
+        self.maps.len() - 1
+    }
+
+    // every texture naming the same file gets the same decoded image, keyed by the canonical path
+    // so different relative spellings still match
+    pub fn load(&mut self, path: &Path, linear: bool) -> Result<usize, ImageError> {
+        let key = (fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf()), linear);
+        if let Some(&index) = self.loaded.get(&key) {
+            return Ok(index);
+        }
+
+        let index = self.add(MipMap::build(Image::load(path, linear)?));
+        self.loaded.insert(key, index);
+
+        Ok(index)
+    }
+
+    pub fn get(&self, index: usize) -> &MipMap {
+        &self.maps[index]
+    }
+}
+
+// gives back width, height, the max sample value and the rgb samples
+fn decode_png(bytes: &[u8]) -> Result<(usize, usize, u32, Vec<u16>), DecodingError> {
+    let mut decoder = Decoder::new(bytes);
+    // palettes and low bit depths come out as plain 8 bit samples
+    decoder.set_transformations(Transformations::EXPAND);
+    let mut reader = decoder.read_info()?;
+    let mut data = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut data)?;
+
+    let wide = info.bit_depth == BitDepth::Sixteen;
+    let values: Vec<u16> = if wide {
+        data[..info.buffer_size()]
+            .chunks_exact(2)
+            .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+            .collect()
+    }
+    else {
+        data[..info.buffer_size()].iter().map(|&value| value as u16).collect()
+    };
+
+    // alpha is dropped and gray gets spread over all three channels
+    let channels = info.color_type.samples();
+    let mut samples = Vec::with_capacity(info.width as usize * info.height as usize * 3);
+    for pixel in values.chunks_exact(channels) {
+        match info.color_type {
+            ColorType::Grayscale | ColorType::GrayscaleAlpha => samples.extend_from_slice(&[pixel[0]; 3]),
+            _ => samples.extend_from_slice(&pixel[..3]),
+        }
+    }
+
+    Ok((info.width as usize, info.height as usize, if wide { 65535 } else { 255 }, samples))
+}
+
+// ascii p3 and binary p6, with 16 bit binary samples big endian as the format asks
+fn decode_ppm(bytes: &[u8]) -> Result<(usize, usize, u32, Vec<u16>), &'static str> {
+    let mut position = 2;
+    let mut header = [0; 3];
+    for value in &mut header {
+        *value = ppm_number(bytes, &mut position).ok_or("header is cut short")?;
+    }
+    let [width, height, max_value] = header;
+    if width == 0 || height == 0 || max_value == 0 || max_value > 65535 {
+        return Err("header values out of range");
+    }
+
+    let count = width as usize * height as usize * 3;
+    let samples: Vec<u16> = if bytes[1] == b'3' {
+        (0..count)
+            .map(|_| ppm_number(bytes, &mut position).map(|value| value.min(max_value) as u16))
+            .collect::<Option<_>>()
+            .ok_or("fewer samples than the header promises")?
+    }
+    else {
+        // exactly one whitespace byte separates the header from the binary data
+        let data = bytes.get(position + 1..).unwrap_or(&[]);
+        if max_value < 256 {
+            data.get(..count)
+                .ok_or("fewer samples than the header promises")?
+                .iter()
+                .map(|&value| value as u16)
+                .collect()
+        }
+        else {
+            let data = data.get(..count * 2).ok_or("fewer samples than the header promises")?;
+            data.chunks_exact(2).map(|pair| u16::from_be_bytes([pair[0], pair[1]])).collect()
+        }
+    };
+
+    Ok((width as usize, height as usize, max_value, samples))
+}
+
+// skips whitespace and # comments, then reads one decimal number
+fn ppm_number(bytes: &[u8], position: &mut usize) -> Option<u32> {
+    loop {
+        match bytes.get(*position)? {
+            b'#' => {
+                while bytes.get(*position).is_some_and(|&byte| byte != b'\n') {
+                    *position += 1;
+                }
+            }
+            byte if byte.is_ascii_whitespace() => *position += 1,
+            _ => break,
+        }
+    }
+
+    let start = *position;
+    while bytes.get(*position).is_some_and(|byte| byte.is_ascii_digit()) {
+        *position += 1;
+    }
+    std::str::from_utf8(&bytes[start..*position]).ok()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tables_share_images_by_file_and_reload_them_when_fresh() {
+        let path = std::env::temp_dir().join(format!("path_tracer_table_{}.ppm", std::process::id()));
+        fs::write(&path, "P3\n1 1\n255\n255 0 0\n").unwrap();
+
+        let mut images = ImageTable::new();
+        let first = images.load(&path, true).unwrap();
+        assert_eq!(images.load(&path, true).unwrap(), first);
+        assert_ne!(images.load(&path, false).unwrap(), first);
+
+        fs::write(&path, "P3\n1 1\n255\n0 0 255\n").unwrap();
+        let mut fresh = ImageTable::new();
+        let index = fresh.load(&path, true).unwrap();
+        let texel = fresh.get(index).base().texel(0, 0);
+        fs::remove_file(&path).unwrap();
+        assert_eq!((texel.x, texel.y, texel.z), (0., 0., 1.));
+    }
+}
@@ -0,0 +1,24 @@
+mod bvh;
+pub mod camera;
+pub mod debug_scenes;
+pub mod geometry;
+pub mod hdr_buffer;
+pub mod image;
+pub mod materials;
+pub mod obj;
+pub mod output;
+pub mod ray_hit;
+#[cfg(feature = "window")]
+pub mod realtime_buffer;
+pub mod scene;
+pub mod scene_file;
+pub mod textures;
+pub mod tonemap;
+pub mod utils;
+pub mod vector;
+
+pub use camera::Camera;
+pub use hdr_buffer::HdrBuffer;
+pub use materials::Material;
+pub use scene::Scene;
+pub use vector::Vec3;
@@ -1,75 +1,131 @@
-mod camera;
-mod debug_scenes;
-mod geometry;
-mod materials;
-mod ray_hit;
-mod realtime_buffer;
-mod scene;
-mod utils;
-mod vector;
+mod cli;
 
 use std::env;
 use std::fs::File;
-use std::path::Path;
+use std::process;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::Duration;
 
-use camera::Camera;
+use cli::CliError;
+use cli::Options;
+use cli::SceneSource;
+use cli::USAGE;
+#[cfg(feature = "window")]
 use minifb::Window;
+#[cfg(feature = "window")]
 use minifb::WindowOptions;
-use realtime_buffer::run_application;
-use realtime_buffer::Buffer;
-use scene::Scene;
-use utils::Int;
-use vector::Vec3;
+use path_tracer::debug_scenes;
+use path_tracer::output;
+#[cfg(feature = "window")]
+use path_tracer::realtime_buffer::run_application;
+use path_tracer::scene_file;
+use path_tracer::utils::Float;
+use path_tracer::utils::Int;
+#[cfg(feature = "window")]
+use path_tracer::HdrBuffer;
+use path_tracer::Scene;
 
-const WIDTH: Int = 1920 / UPSCALE;
-const HEIGHT: Int = 1080 / UPSCALE;
+// the interactive preview runs at a fraction of the final resolution
 const UPSCALE: Int = 5;
 
 fn main() {
-    let mut camera = Camera {
-        height: HEIGHT,
-        width: WIDTH,
-        position: Vec3::build(0., 2., 7.),
-        fov: 55.,
-        focal_length: 1.,
-        yaw: 3.141934 / 2.,
-        pitch: 0.,
-        move_speed: 0.15,
-        rotation_speed: 0.03,
-        max_recursive_depth: 4,
-        denoise_iters: 1,
-        samples: 1,
-        ..Default::default()
+    let options = match Options::parse(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(CliError::Help) => {
+            println!("{}", USAGE);
+            return;
+        }
+        Err(error) => {
+            eprintln!("error: {}\n\n{}", error, USAGE);
+            process::exit(2);
+        }
     };
-    camera.build_params();
-    let mut scene = Scene::new();
-    debug_scenes::cornell_basic(&mut scene);
 
-    let envs: Vec<String> = env::args().collect();
-    let path = match envs.get(1) {
-        Some(env) => Path::new(env),
-        None => Path::new("../images/dump.ppm"),
+    let (scene, mut camera) = match &options.scene {
+        SceneSource::Builtin(name) => {
+            let mut scene = Scene::new();
+            let build_scene = debug_scenes::by_name(name).expect("scene names are checked while parsing");
+            build_scene(&mut scene);
+            (scene, scene_file::default_camera())
+        }
+        SceneSource::File(path) => scene_file::load(path).unwrap_or_else(|error| {
+            eprintln!("error: {}", error);
+            process::exit(1);
+        }),
     };
-    let mut file = File::create(path).expect("failed to create file");
-    let mut window = Window::new(
-        "cpu path traced",
-        WIDTH as usize,
-        HEIGHT as usize,
-        WindowOptions { scale: minifb::Scale::X4, ..Default::default() },
-    )
-    .expect("failed to get minifb window handle");
-    let mut buffer = Buffer::build(HEIGHT as usize, WIDTH as usize);
+    scene.build();
+    if let Err(error) = options.apply(&mut camera) {
+        eprintln!("error: {}\n\n{}", error, USAGE);
+        process::exit(2);
+    }
+
+    // the preview is cheaper than the final render, which gets these back once the window closes
+    let (width, height, samples, max_depth) = (camera.width, camera.height, camera.samples, camera.max_depth);
+    camera.width = (width / UPSCALE).max(1);
+    camera.height = (height / UPSCALE).max(1);
+    camera.samples = 1;
+    camera.max_depth = Some(4);
+    camera.build_params();
+
+    // create the file up front so a bad path fails before any rendering happens
+    let mut file = File::create(&options.output).unwrap_or_else(|error| {
+        eprintln!("error: failed to create {}: {}", options.output.display(), error);
+        process::exit(1);
+    });
+
+    #[cfg(feature = "window")]
+    if !options.headless {
+        let mut window = Window::new(
+            "cpu path traced",
+            camera.width as usize,
+            camera.height as usize,
+            WindowOptions { scale: minifb::Scale::X4, ..Default::default() },
+        )
+        .expect("failed to get minifb window handle");
+        let mut buffer = HdrBuffer::build(camera.height as usize, camera.width as usize);
 
-    run_application(&mut camera, &mut buffer, &scene, &mut window);
+        run_application(&mut camera, &mut buffer, &scene, &mut window);
+    }
+    #[cfg(not(feature = "window"))]
+    if !options.headless {
+        println!("built without the window feature, rendering headless");
+    }
 
-    camera.height *= UPSCALE;
-    camera.width *= UPSCALE;
-    camera.samples = 30;
-    camera.max_recursive_depth = 30;
+    camera.height = height;
+    camera.width = width;
+    camera.samples = samples;
+    camera.max_depth = max_depth;
     camera.build_params();
     println!("beginning image processing");
     let start_time = std::time::Instant::now();
-    camera.render_to_file_par(&mut file, &scene).expect("failed rendering image: io failure");
+    let total_pixels = (camera.width * camera.height) as usize;
+    let progress = AtomicUsize::new(0);
+    let buffer = thread::scope(|scope| {
+        scope.spawn(|| print_progress(&progress, total_pixels));
+        camera.render_par(&scene, &progress)
+    });
+    let layers = if options.aovs { camera.render_aovs(&scene) } else { Vec::new() };
+    let written = output::write(&buffer, &layers, &mut file, options.format, options.depth, &camera.tonemap);
+    if let Err(error) = written {
+        eprintln!("error: failed writing {}: {}", options.output.display(), error);
+        process::exit(1);
+    }
     let end_time = start_time.elapsed();
     println!("process time: {} seconds\n{} minutes", end_time.as_secs_f32(), end_time.as_secs_f32() / 60.);
 }
+
+// prints how far the render is once a second until every pixel is done
+fn print_progress(progress: &AtomicUsize, total_pixels: usize) {
+    loop {
+        let done = progress.load(Ordering::Relaxed);
+        println!("progress: {:.2}%", done as Float / total_pixels as Float * 100.);
+
+        if done >= total_pixels {
+            break;
+        }
+
+        thread::sleep(Duration::from_millis(1000));
+    }
+}
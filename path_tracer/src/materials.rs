@@ -1,9 +1,11 @@
 use crate::ray_hit::HitRecord;
-use crate::ray_hit::Ray;
 use crate::ray_hit::Scatter;
 use crate::ray_hit::ScatterRecord;
+use crate::textures::NormalMap;
+use crate::textures::Texture;
 use crate::utils::random;
 use crate::utils::Float;
+use crate::utils::PI;
 use crate::vector::Vec3;
 
 #[derive(Clone, Copy)]
@@ -23,6 +25,48 @@ impl Material {
             Material::Source(source) => source.scatter(record, scatter_record),
         }
     }
+
+    pub fn evaluate(&self, record: &HitRecord, direction: &Vec3) -> Vec3 {
+        match self {
+            Material::Lambertian(lambertian) => lambertian.evaluate(record, direction),
+            Material::Metal(metal) => metal.evaluate(record, direction),
+            Material::Glass(glass) => glass.evaluate(record, direction),
+            Material::Source(source) => source.evaluate(record, direction),
+        }
+    }
+
+    pub fn pdf(&self, record: &HitRecord, direction: &Vec3) -> Float {
+        match self {
+            Material::Lambertian(lambertian) => lambertian.pdf(record, direction),
+            Material::Metal(metal) => metal.pdf(record, direction),
+            Material::Glass(glass) => glass.pdf(record, direction),
+            Material::Source(source) => source.pdf(record, direction),
+        }
+    }
+
+    pub fn texture(&self) -> Texture {
+        match self {
+            Material::Lambertian(lambertian) => lambertian.albedo,
+            Material::Metal(metal) => metal.albedo,
+            Material::Glass(glass) => glass.albedo,
+            Material::Source(source) => Texture::Solid(source.albedo),
+        }
+    }
+
+    pub fn normal_map(&self) -> Option<NormalMap> {
+        match self {
+            Material::Lambertian(lambertian) => lambertian.normal_map,
+            Material::Metal(metal) => metal.normal_map,
+            _ => None,
+        }
+    }
+
+    pub fn emission(&self) -> Vec3 {
+        match self {
+            Material::Source(source) => source.albedo,
+            _ => Vec3::zeros(),
+        }
+    }
 }
 
 impl Default for Material {
@@ -33,60 +77,347 @@ impl Default for Material {
 
 #[derive(Clone, Copy)]
 pub struct Lambertian {
-    pub albedo: Vec3,
+    pub albedo: Texture,
+    pub normal_map: Option<NormalMap>,
 }
 
 impl Lambertian {
-    pub fn build(albedo: Vec3) -> Self {
-        Lambertian { albedo }
+    pub fn build(albedo: impl Into<Texture>) -> Self {
+        Lambertian { albedo: albedo.into(), normal_map: None }
+    }
+
+    pub const fn with_normal_map(mut self, normal_map: Option<NormalMap>) -> Self {
+        self.normal_map = normal_map;
+        self
     }
 }
 
 impl Scatter for Lambertian {
     fn scatter(&self, hitrecord: &HitRecord, record: &mut ScatterRecord) -> bool {
-        let mut scatter_direction = hitrecord.normal + Vec3::random_unit_vector();
+        let mut scatter_direction = hitrecord.shading_normal + Vec3::random_unit_vector();
         if scatter_direction.near_zero() {
-            scatter_direction = hitrecord.normal;
+            scatter_direction = hitrecord.shading_normal;
         }
-        record.scattered = Ray::build(hitrecord.point, scatter_direction);
-        record.attenuation = self.albedo;
+        record.scattered = hitrecord.spawn_ray(scatter_direction);
+        record.pdf = self.pdf(hitrecord, &scatter_direction.normalized());
+        record.specular = false;
+        // a tilted shading normal can send the sample through the actual surface, which evaluate
+        // gives nothing for, so the path ends there instead of leaking light through
+        record.attenuation = if scatter_direction.inner_product(&hitrecord.normal) <= 0. {
+            Vec3::zeros()
+        }
+        else {
+            hitrecord.albedo
+        };
 
         true
     }
+
+    fn evaluate(&self, hitrecord: &HitRecord, direction: &Vec3) -> Vec3 {
+        if direction.inner_product(&hitrecord.normal) <= 0. {
+            return Vec3::zeros();
+        }
+        hitrecord.albedo * (direction.inner_product(&hitrecord.shading_normal).max(0.) / PI)
+    }
+
+    fn pdf(&self, hitrecord: &HitRecord, direction: &Vec3) -> Float {
+        direction.inner_product(&hitrecord.shading_normal).max(0.) / PI
+    }
 }
 
+// a conductor with a ggx microfacet distribution, roughness is the perceptual kind that gets
+// squared into the distribution's alpha, and the albedo is the reflectance head on that schlick's
+// fresnel brightens toward grazing angles
 #[derive(Clone, Copy)]
 pub struct Metal {
-    pub albedo: Vec3,
-    pub fuzzy: Float,
+    pub albedo: Texture,
+    pub roughness: Float,
+    pub normal_map: Option<NormalMap>,
 }
 
+// below this alpha the distribution is too sharp to sample or evaluate in single precision, so it
+// becomes a perfect mirror
+const MIN_ALPHA: Float = 1e-3;
+
 impl Metal {
-    pub fn build(albedo: Vec3, fuzzy: Float) -> Self {
-        Metal { albedo, fuzzy }
+    pub fn build(albedo: impl Into<Texture>, roughness: Float) -> Self {
+        Metal { albedo: albedo.into(), roughness, normal_map: None }
+    }
+
+    pub const fn with_normal_map(mut self, normal_map: Option<NormalMap>) -> Self {
+        self.normal_map = normal_map;
+        self
+    }
+
+    fn alpha(&self) -> Float {
+        self.roughness * self.roughness
+    }
+
+    fn fresnel(&self, hitrecord: &HitRecord, cos: Float) -> Vec3 {
+        let reflectance = hitrecord.albedo;
+        reflectance + (Vec3::build(1., 1., 1.) - reflectance) * (1. - cos.clamp(0., 1.)).powi(5)
     }
 }
 
 impl Scatter for Metal {
     fn scatter(&self, hitrecord: &HitRecord, record: &mut ScatterRecord) -> bool {
-        let mut reflected = hitrecord.ray_in.reflect_around(&hitrecord.normal).normalized();
-        reflected += Vec3::random_unit_vector() * self.fuzzy;
-        record.scattered = Ray::build(hitrecord.point, reflected);
-        record.attenuation = self.albedo;
+        let frame = Frame::build(&hitrecord.shading_normal);
+        let outgoing = frame.to_local(&-hitrecord.ray_in.normalized());
+        record.specular = self.alpha() < MIN_ALPHA;
+        if record.specular {
+            let reflected = hitrecord.ray_in.reflect_around(&hitrecord.shading_normal);
+            record.scattered = hitrecord.spawn_ray(reflected);
+            record.attenuation = self.fresnel(hitrecord, outgoing.z);
+            record.pdf = 0.;
+            return true;
+        }
+
+        let alpha = self.alpha();
+        let microfacet = sample_visible_normal(&outgoing, alpha);
+        let incoming = (-outgoing).reflect_around(&microfacet);
+        record.scattered = hitrecord.spawn_ray(frame.to_world(&incoming));
+        record.pdf = self.pdf(hitrecord, &record.scattered.direction);
+        // f * cos / pdf, where the distribution cancels and only the masking of the incoming side
+        // is left over from what sampling visible normals already accounted for
+        record.attenuation = if outgoing.z <= 0. || incoming.z <= 0. {
+            Vec3::zeros()
+        }
+        else {
+            let masking = ggx_masking(&outgoing, &incoming, alpha) / ggx_masking_one(&outgoing, alpha);
+            self.fresnel(hitrecord, incoming.inner_product(&microfacet)) * masking
+        };
 
         true
     }
+
+    fn evaluate(&self, hitrecord: &HitRecord, direction: &Vec3) -> Vec3 {
+        if self.alpha() < MIN_ALPHA || direction.inner_product(&hitrecord.normal) <= 0. {
+            return Vec3::zeros();
+        }
+
+        let frame = Frame::build(&hitrecord.shading_normal);
+        let outgoing = frame.to_local(&-hitrecord.ray_in.normalized());
+        let incoming = frame.to_local(&direction.normalized());
+        if outgoing.z <= 0. || incoming.z <= 0. {
+            return Vec3::zeros();
+        }
+
+        let alpha = self.alpha();
+        let microfacet = (outgoing + incoming).normalized();
+        let distribution = ggx_distribution(&microfacet, alpha);
+        let masking = ggx_masking(&outgoing, &incoming, alpha);
+        let fresnel = self.fresnel(hitrecord, incoming.inner_product(&microfacet));
+        fresnel * (distribution * masking / (4. * outgoing.z))
+    }
+
+    // the density of visible normals, carried over to the reflected direction
+    fn pdf(&self, hitrecord: &HitRecord, direction: &Vec3) -> Float {
+        if self.alpha() < MIN_ALPHA {
+            return 0.;
+        }
+
+        let frame = Frame::build(&hitrecord.shading_normal);
+        let outgoing = frame.to_local(&-hitrecord.ray_in.normalized());
+        let incoming = frame.to_local(&direction.normalized());
+        if outgoing.z <= 0. || incoming.z <= 0. {
+            return 0.;
+        }
+
+        let alpha = self.alpha();
+        let microfacet = (outgoing + incoming).normalized();
+        ggx_masking_one(&outgoing, alpha) * ggx_distribution(&microfacet, alpha) / (4. * outgoing.z)
+    }
+}
+
+// shading space with the normal along z, which is where the microfacet formulas live
+struct Frame {
+    tangent: Vec3,
+    bitangent: Vec3,
+    normal: Vec3,
+}
+
+impl Frame {
+    fn build(normal: &Vec3) -> Self {
+        let (tangent, bitangent) = normal.orthonormal_basis();
+        Frame { tangent, bitangent, normal: *normal }
+    }
+
+    fn to_local(&self, vector: &Vec3) -> Vec3 {
+        Vec3::build(
+            vector.inner_product(&self.tangent),
+            vector.inner_product(&self.bitangent),
+            vector.inner_product(&self.normal),
+        )
+    }
+
+    fn to_world(&self, vector: &Vec3) -> Vec3 {
+        self.tangent * vector.x + self.bitangent * vector.y + self.normal * vector.z
+    }
+}
+
+// trowbridge reitz, the fraction of microfacets facing along microfacet per unit of solid angle
+fn ggx_distribution(microfacet: &Vec3, alpha: Float) -> Float {
+    let alpha_squared = alpha * alpha;
+    let denominator = microfacet.z * microfacet.z * (alpha_squared - 1.) + 1.;
+    alpha_squared / (PI * denominator * denominator)
 }
 
+// smith's lambda, how much of the surface is hidden from direction per unit that is visible
+fn ggx_lambda(direction: &Vec3, alpha: Float) -> Float {
+    let cos_squared = direction.z * direction.z;
+    let tan_squared = (1. - cos_squared).max(0.) / cos_squared;
+    0.5 * ((1. + alpha * alpha * tan_squared).sqrt() - 1.)
+}
+
+fn ggx_masking_one(direction: &Vec3, alpha: Float) -> Float {
+    1. / (1. + ggx_lambda(direction, alpha))
+}
+
+// height correlated masking and shadowing for the pair of directions
+fn ggx_masking(outgoing: &Vec3, incoming: &Vec3, alpha: Float) -> Float {
+    1. / (1. + ggx_lambda(outgoing, alpha) + ggx_lambda(incoming, alpha))
+}
+
+// heitz's sampling of the normals visible from outgoing, done by stretching the distribution into a
+// hemisphere, picking a point on the disk it projects to and stretching back
+fn sample_visible_normal(outgoing: &Vec3, alpha: Float) -> Vec3 {
+    let stretched = Vec3::build(alpha * outgoing.x, alpha * outgoing.y, outgoing.z).normalized();
+    let length_squared = stretched.x * stretched.x + stretched.y * stretched.y;
+    let first = if length_squared > 0. {
+        Vec3::build(-stretched.y, stretched.x, 0.) / length_squared.sqrt()
+    }
+    else {
+        Vec3::build(1., 0., 0.)
+    };
+    let second = stretched.cross_product(&first);
+
+    let radius = random().sqrt();
+    let phi = 2. * PI * random();
+    let x = radius * phi.cos();
+    let blend = 0.5 * (1. + stretched.z);
+    let y = (1. - blend) * (1. - x * x).sqrt() + blend * radius * phi.sin();
+    let normal = first * x + second * y + stretched * (1. - x * x - y * y).max(0.).sqrt();
+
+    Vec3::build(alpha * normal.x, alpha * normal.y, normal.z.max(0.)).normalized()
+}
+
+// roughness works like the metal's, zero keeps the perfectly smooth surface
 #[derive(Clone, Copy)]
 pub struct Glass {
-    pub albedo: Vec3,
+    pub albedo: Texture,
     pub refraction_index: Float,
+    pub roughness: Float,
 }
 
 impl Glass {
-    pub fn build(albedo: Vec3, refraction_index: Float) -> Self {
-        Glass { albedo, refraction_index }
+    pub fn build(albedo: impl Into<Texture>, refraction_index: Float) -> Self {
+        Glass { albedo: albedo.into(), refraction_index, roughness: 0. }
+    }
+
+    pub const fn with_roughness(mut self, roughness: Float) -> Self {
+        self.roughness = roughness;
+        self
+    }
+
+    fn alpha(&self) -> Float {
+        self.roughness * self.roughness
+    }
+
+    // ratio of the refraction index on the far side of the surface to the near side
+    fn relative_index(&self, hitrecord: &HitRecord) -> Float {
+        if hitrecord.front_face {
+            self.refraction_index
+        }
+        else {
+            1. / self.refraction_index
+        }
+    }
+
+    // walter et al's microfacet reflection and transmission, gives back the bsdf times the cosine
+    // and the pdf together since they share nearly everything, both zero for the smooth surface
+    fn rough_terms(&self, hitrecord: &HitRecord, direction: &Vec3) -> (Float, Float) {
+        let alpha = self.alpha();
+        if alpha < MIN_ALPHA {
+            return (0., 0.);
+        }
+
+        let frame = Frame::build(&hitrecord.shading_normal);
+        let outgoing = frame.to_local(&-hitrecord.ray_in.normalized());
+        let incoming = frame.to_local(&direction.normalized());
+        if outgoing.z <= 0. || incoming.z == 0. {
+            return (0., 0.);
+        }
+
+        let eta = self.relative_index(hitrecord);
+        let reflecting = incoming.z > 0.;
+        // the generalized half vector, which for transmission weighs each side by its index
+        let mut microfacet = if reflecting { outgoing + incoming } else { outgoing + incoming * eta };
+        if microfacet.near_zero() {
+            return (0., 0.);
+        }
+        microfacet = microfacet.normalized();
+        if microfacet.z < 0. {
+            microfacet = -microfacet;
+        }
+
+        let cos_out = outgoing.inner_product(&microfacet);
+        let cos_in = incoming.inner_product(&microfacet);
+        // microfacets that face away from either direction, or that would have to refract toward
+        // the wrong side, can't connect the two
+        if cos_out <= 0. || (reflecting && cos_in <= 0.) || (!reflecting && cos_in >= 0.) {
+            return (0., 0.);
+        }
+
+        let fresnel = fresnel_dielectric(cos_out, eta);
+        let distribution = ggx_distribution(&microfacet, alpha);
+        let visible = ggx_masking_one(&outgoing, alpha) * distribution / outgoing.z;
+        let masking = ggx_masking(&outgoing, &incoming, alpha);
+        if reflecting {
+            let bsdf = fresnel * distribution * masking / (4. * outgoing.z);
+            (bsdf, fresnel * visible / 4.)
+        }
+        else {
+            // how much solid angle around the microfacet maps to the refracted direction
+            let spread = (cos_in + cos_out / eta).powi(2);
+            let bsdf =
+                (1. - fresnel) * distribution * masking * (cos_in * cos_out).abs() / (outgoing.z * spread);
+            (bsdf, (1. - fresnel) * visible * cos_out * cos_in.abs() / spread)
+        }
+    }
+
+    // picks reflection or refraction off a visible microfacet by its fresnel term, which leaves the
+    // same masking ratio as the metal for either, and like the smooth surface it skips scaling
+    // radiance by the squared index ratio since that cancels for light that goes in and back out
+    fn scatter_rough(&self, hitrecord: &HitRecord, record: &mut ScatterRecord) -> bool {
+        let alpha = self.alpha();
+        let frame = Frame::build(&hitrecord.shading_normal);
+        let outgoing = frame.to_local(&-hitrecord.ray_in.normalized());
+        let eta = self.relative_index(hitrecord);
+        let microfacet = sample_visible_normal(&outgoing, alpha);
+        let fresnel = fresnel_dielectric(outgoing.inner_product(&microfacet), eta);
+        let reflecting = random() < fresnel;
+        let incoming = if reflecting {
+            (-outgoing).reflect_around(&microfacet)
+        }
+        else {
+            (-outgoing).refract_around(&microfacet, 1. / eta)
+        };
+
+        record.scattered = hitrecord.spawn_ray(frame.to_world(&incoming));
+        record.pdf = self.pdf(hitrecord, &record.scattered.direction);
+        record.specular = false;
+        // a reflection off a steep microfacet can still end up below the surface and a refraction
+        // above it, those paths are lost instead of being counted as the other kind
+        let wrong_side = (incoming.z > 0.) != reflecting;
+        record.attenuation = if outgoing.z <= 0. || record.pdf <= 0. || wrong_side {
+            Vec3::zeros()
+        }
+        else {
+            let masking = ggx_masking(&outgoing, &incoming, alpha) / ggx_masking_one(&outgoing, alpha);
+            hitrecord.albedo * masking
+        };
+
+        true
     }
 
     pub fn reflectance(&self, cos: Float) -> Float {
@@ -97,6 +428,10 @@ impl Glass {
 
 impl Scatter for Glass {
     fn scatter(&self, hitrecord: &HitRecord, record: &mut ScatterRecord) -> bool {
+        if self.alpha() >= MIN_ALPHA {
+            return self.scatter_rough(hitrecord, record);
+        }
+
         let refraction_index = if hitrecord.front_face {
             1. / self.refraction_index
         }
@@ -104,20 +439,48 @@ impl Scatter for Glass {
             self.refraction_index
         };
         let unit_direction = hitrecord.ray_in.normalized();
-        let cos = -unit_direction.inner_product(&hitrecord.normal).min(1.);
+        let normal = hitrecord.shading_normal;
+        let cos = -unit_direction.inner_product(&normal).min(1.);
         let sin = (1. - cos * cos).sqrt();
         let direction = if refraction_index * sin > 1. || self.reflectance(cos) > random() {
-            unit_direction.reflect_around(&hitrecord.normal)
+            unit_direction.reflect_around(&normal)
         }
         else {
-            unit_direction.refract_around(&hitrecord.normal, refraction_index)
+            unit_direction.refract_around(&normal, refraction_index)
         };
 
-        record.scattered = Ray::build(hitrecord.point, direction);
-        record.attenuation = self.albedo;
+        record.scattered = hitrecord.spawn_ray(direction);
+        record.attenuation = hitrecord.albedo;
+        record.pdf = 0.;
+        record.specular = true;
 
         true
     }
+
+    fn evaluate(&self, hitrecord: &HitRecord, direction: &Vec3) -> Vec3 {
+        let (bsdf, _) = self.rough_terms(hitrecord, direction);
+        if bsdf <= 0. {
+            return Vec3::zeros();
+        }
+        hitrecord.albedo * bsdf
+    }
+
+    fn pdf(&self, hitrecord: &HitRecord, direction: &Vec3) -> Float {
+        self.rough_terms(hitrecord, direction).1
+    }
+}
+
+// exact fresnel reflectance for unpolarized light, one past the critical angle
+fn fresnel_dielectric(cos: Float, eta: Float) -> Float {
+    let sin_squared = (1. - cos * cos).max(0.) / (eta * eta);
+    if sin_squared >= 1. {
+        return 1.;
+    }
+
+    let cos_refracted = (1. - sin_squared).sqrt();
+    let parallel = (eta * cos - cos_refracted) / (eta * cos + cos_refracted);
+    let perpendicular = (cos - eta * cos_refracted) / (cos + eta * cos_refracted);
+    0.5 * (parallel * parallel + perpendicular * perpendicular)
 }
 
 #[derive(Clone, Copy)]
@@ -136,4 +499,149 @@ impl Scatter for Source {
         record.attenuation = self.albedo;
         false
     }
+
+    fn evaluate(&self, _hitrecord: &HitRecord, _direction: &Vec3) -> Vec3 {
+        Vec3::zeros()
+    }
+
+    fn pdf(&self, _hitrecord: &HitRecord, _direction: &Vec3) -> Float {
+        0.
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::ImageTable;
+    use crate::utils::seed_pixel;
+
+    const SAMPLES: usize = 50_000;
+
+    // a surface facing up, lit from the given angle off the normal on either side
+    fn surface_hit(material: Material, degrees: Float, front_face: bool) -> HitRecord {
+        let angle = degrees.to_radians();
+        let mut record = HitRecord::new();
+        record.ray_in = Vec3::build(angle.sin(), -angle.cos(), 0.);
+        record.normal = Vec3::build(0., 1., 0.);
+        record.shading_normal = record.normal;
+        record.front_face = front_face;
+        record.material = material;
+        record.resolve_textures(&ImageTable::new());
+        record
+    }
+
+    fn glass_hit(roughness: Float, degrees: Float, front_face: bool) -> (Glass, HitRecord) {
+        let glass = Glass::build(Vec3::build(1., 1., 1.), 1.5).with_roughness(roughness);
+        (glass, surface_hit(Material::Glass(glass), degrees, front_face))
+    }
+
+    // monte carlo estimate of how much of the incoming energy leaves again, reflected or refracted
+    fn albedo(glass: &Glass, record: &HitRecord) -> Float {
+        let mut total = 0.;
+        for _ in 0..SAMPLES {
+            let mut scatter = ScatterRecord::new();
+            assert!(glass.scatter(record, &mut scatter));
+            total += scatter.attenuation.x;
+        }
+        total / SAMPLES as Float
+    }
+
+    #[test]
+    fn glass_never_creates_energy() {
+        for (index, roughness) in [0., 0.1, 0.3, 0.6, 1.].into_iter().enumerate() {
+            for degrees in [0., 45., 80.] {
+                for front_face in [true, false] {
+                    seed_pixel(7, index, 1);
+                    let (glass, record) = glass_hit(roughness, degrees, front_face);
+                    let albedo = albedo(&glass, &record);
+                    assert!(albedo <= 1. + 1e-3, "{} at {} degrees: {}", roughness, degrees, albedo);
+                }
+            }
+        }
+    }
+
+    // rougher glass is expected to lose some energy to the single scattering microfacet model
+    #[test]
+    fn smooth_and_slightly_rough_glass_keep_their_energy() {
+        for roughness in [0., 0.1] {
+            for degrees in [0., 45., 80.] {
+                for front_face in [true, false] {
+                    seed_pixel(7, 0, 1);
+                    let (glass, record) = glass_hit(roughness, degrees, front_face);
+                    let albedo = albedo(&glass, &record);
+                    assert!(albedo > 0.995, "{} at {} degrees: {}", roughness, degrees, albedo);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rough_glass_samples_match_its_evaluate_and_pdf() {
+        for roughness in [0.1, 0.3, 0.6, 1.] {
+            for degrees in [0., 45., 80.] {
+                for front_face in [true, false] {
+                    seed_pixel(7, 0, 1);
+                    let (glass, record) = glass_hit(roughness, degrees, front_face);
+                    for _ in 0..SAMPLES / 10 {
+                        let mut scatter = ScatterRecord::new();
+                        glass.scatter(&record, &mut scatter);
+                        if scatter.attenuation.x <= 0. {
+                            continue;
+                        }
+                        let direction = scatter.scattered.direction;
+                        let weight = glass.evaluate(&record, &direction).x / glass.pdf(&record, &direction);
+                        let error = (weight / scatter.attenuation.x - 1.).abs();
+                        assert!(error < 1e-3, "{} at {} degrees: {}", roughness, degrees, error);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rough_metal_samples_match_its_evaluate_and_pdf() {
+        for roughness in [0.1, 0.3, 0.6, 1.] {
+            for degrees in [0., 45., 80.] {
+                seed_pixel(7, 0, 1);
+                let metal = Metal::build(Vec3::build(0.9, 0.6, 0.3), roughness);
+                let record = surface_hit(Material::Metal(metal), degrees, true);
+                for _ in 0..SAMPLES / 10 {
+                    let mut scatter = ScatterRecord::new();
+                    metal.scatter(&record, &mut scatter);
+                    if scatter.attenuation.x <= 0. {
+                        continue;
+                    }
+                    let direction = scatter.scattered.direction;
+                    let weight = metal.evaluate(&record, &direction) / metal.pdf(&record, &direction);
+                    let attenuation = scatter.attenuation;
+                    for (weight, attenuation) in
+                        [(weight.x, attenuation.x), (weight.y, attenuation.y), (weight.z, attenuation.z)]
+                    {
+                        let error = (weight / attenuation - 1.).abs();
+                        assert!(error < 1e-3, "{} at {} degrees: {}", roughness, degrees, error);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn lambertian_samples_stay_above_the_surface_with_a_tilted_normal() {
+        seed_pixel(7, 0, 1);
+        let lambertian = Lambertian::build(Vec3::build(0.8, 0.8, 0.8));
+        let mut record = surface_hit(Material::Lambertian(lambertian), 30., true);
+        record.shading_normal = Vec3::build(0.8, 0.6, 0.);
+        for _ in 0..SAMPLES / 10 {
+            let mut scatter = ScatterRecord::new();
+            lambertian.scatter(&record, &mut scatter);
+            let direction = scatter.scattered.direction;
+            let weight = lambertian.evaluate(&record, &direction).x / lambertian.pdf(&record, &direction);
+            if direction.inner_product(&record.normal) <= 0. {
+                assert_eq!(scatter.attenuation.x, 0.);
+            }
+            else {
+                assert!((weight - scatter.attenuation.x).abs() < 1e-4);
+            }
+        }
+    }
 }
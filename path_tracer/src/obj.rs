@@ -0,0 +1,418 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::path::Path;
+use std::path::PathBuf;
+
+use crate::image::ImageError;
+use crate::image::ImageTable;
+use crate::materials::Glass;
+use crate::materials::Lambertian;
+use crate::materials::Material;
+use crate::materials::Metal;
+use crate::materials::Source;
+use crate::textures::Filter;
+use crate::textures::ImageTexture;
+use crate::textures::NormalMap;
+use crate::textures::Texture;
+use crate::textures::Wrap;
+use crate::utils::Float;
+use crate::vector::Vec3;
+
+#[derive(Debug)]
+pub enum ObjErrorKind {
+    Io(io::Error),
+    MissingValue(&'static str),
+    InvalidNumber(String),
+    InvalidIndex(String),
+    IndexOutOfRange(i64),
+    TooFewVertices(usize),
+    UnknownMaterial(String),
+    Image(Box<ImageError>),
+}
+
+#[derive(Debug)]
+pub struct ObjError {
+    pub path: PathBuf,
+    pub line: usize,
+    pub kind: ObjErrorKind,
+}
+
+impl ObjError {
+    fn build(path: &Path, line: usize, kind: ObjErrorKind) -> Self {
+        ObjError { path: path.to_path_buf(), line, kind }
+    }
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: ", self.path.display(), self.line)?;
+        match &self.kind {
+            ObjErrorKind::Io(error) => write!(f, "io failure: {}", error),
+            ObjErrorKind::MissingValue(record) => write!(f, "missing value in `{}` record", record),
+            ObjErrorKind::InvalidNumber(value) => write!(f, "invalid number `{}`", value),
+            ObjErrorKind::InvalidIndex(value) => write!(f, "invalid face index `{}`", value),
+            ObjErrorKind::IndexOutOfRange(index) => write!(f, "face index {} out of range", index),
+            ObjErrorKind::TooFewVertices(count) => write!(f, "face needs at least 3 vertices, got {}", count),
+            ObjErrorKind::UnknownMaterial(name) => write!(f, "unknown material `{}`", name),
+            ObjErrorKind::Image(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for ObjError {}
+
+#[derive(Clone, Copy)]
+pub struct FaceVertex {
+    pub position: usize,
+    pub uv: Option<usize>,
+    pub normal: Option<usize>,
+}
+
+// faces are already fan triangulated, material indexes into ObjModel::materials with None meaning
+// no usemtl was active and the caller's fallback applies
+pub struct ObjFace {
+    pub vertices: [FaceVertex; 3],
+    pub material: Option<usize>,
+}
+
+#[derive(Default)]
+pub struct ObjModel {
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    pub uvs: Vec<(Float, Float)>,
+    pub faces: Vec<ObjFace>,
+    pub materials: Vec<Material>,
+}
+
+impl ObjModel {
+    // textures the mtl files use are loaded into images
+    pub fn load(path: &Path, images: &mut ImageTable) -> Result<Self, ObjError> {
+        let file = File::open(path).map_err(|error| ObjError::build(path, 0, ObjErrorKind::Io(error)))?;
+        ObjModel::parse(BufReader::new(file), path, images)
+    }
+
+    // path is only used for errors and to find mtl files relative to it
+    pub fn parse(reader: impl BufRead, path: &Path, images: &mut ImageTable) -> Result<Self, ObjError> {
+        let mut model = ObjModel::default();
+        let mut material_names: HashMap<String, usize> = HashMap::new();
+        let mut current_material = None;
+
+        for (number, line) in reader.lines().enumerate() {
+            let number = number + 1;
+            let error = |kind| ObjError::build(path, number, kind);
+            let line = line.map_err(|io_error| error(ObjErrorKind::Io(io_error)))?;
+            let mut tokens = line.split_whitespace();
+            let Some(record) = tokens.next()
+            else {
+                continue;
+            };
+
+            match record {
+                "v" => model.positions.push(parse_vec3(&mut tokens, "v").map_err(error)?),
+                "vn" => model.normals.push(parse_vec3(&mut tokens, "vn").map_err(error)?),
+                "vt" => {
+                    let u = parse_float(tokens.next(), "vt").map_err(error)?;
+                    // the v coordinate is optional for 1d textures
+                    let v = match tokens.next() {
+                        Some(token) => parse_float(Some(token), "vt").map_err(error)?,
+                        None => 0.,
+                    };
+                    model.uvs.push((u, v));
+                }
+                "f" => {
+                    let mut vertices = Vec::new();
+                    for token in tokens {
+                        vertices.push(model.parse_face_vertex(token).map_err(error)?);
+                    }
+                    if vertices.len() < 3 {
+                        return Err(error(ObjErrorKind::TooFewVertices(vertices.len())));
+                    }
+                    for i in 1..vertices.len() - 1 {
+                        model.faces.push(ObjFace {
+                            vertices: [vertices[0], vertices[i], vertices[i + 1]],
+                            material: current_material,
+                        });
+                    }
+                }
+                "mtllib" => {
+                    let directory = path.parent().unwrap_or(Path::new(""));
+                    for name in tokens {
+                        let mtl_path = directory.join(name);
+                        for (name, material) in parse_mtl(&mtl_path, images)? {
+                            material_names.insert(name, model.materials.len());
+                            model.materials.push(material);
+                        }
+                    }
+                }
+                "usemtl" => {
+                    let name = tokens.next().ok_or_else(|| error(ObjErrorKind::MissingValue("usemtl")))?;
+                    let index = material_names
+                        .get(name)
+                        .ok_or_else(|| error(ObjErrorKind::UnknownMaterial(name.to_string())))?;
+                    current_material = Some(*index);
+                }
+                // groups, smoothing groups, lines and the rest don't matter for rendering
+                _ => {}
+            }
+        }
+
+        Ok(model)
+    }
+
+    pub fn transform(&mut self, scale: Float, offset: Vec3) {
+        self.positions.iter_mut().for_each(|position| *position = *position * scale + offset);
+    }
+
+    fn parse_face_vertex(&self, token: &str) -> Result<FaceVertex, ObjErrorKind> {
+        let mut parts = token.split('/');
+        let position = parts.next().ok_or(ObjErrorKind::MissingValue("f"))?;
+        let position = resolve_index(position, self.positions.len())?;
+        let uv = match parts.next() {
+            Some("") | None => None,
+            Some(uv) => Some(resolve_index(uv, self.uvs.len())?),
+        };
+        let normal = match parts.next() {
+            Some("") | None => None,
+            Some(normal) => Some(resolve_index(normal, self.normals.len())?),
+        };
+
+        Ok(FaceVertex { position, uv, normal })
+    }
+}
+
+// obj indices start at 1 and negative ones count back from the most recent element
+fn resolve_index(token: &str, count: usize) -> Result<usize, ObjErrorKind> {
+    let index: i64 = token.parse().map_err(|_| ObjErrorKind::InvalidIndex(token.to_string()))?;
+    let resolved = if index < 0 { count as i64 + index } else { index - 1 };
+    if index == 0 || resolved < 0 || resolved >= count as i64 {
+        return Err(ObjErrorKind::IndexOutOfRange(index));
+    }
+
+    Ok(resolved as usize)
+}
+
+fn parse_float(token: Option<&str>, record: &'static str) -> Result<Float, ObjErrorKind> {
+    let token = token.ok_or(ObjErrorKind::MissingValue(record))?;
+    token.parse().map_err(|_| ObjErrorKind::InvalidNumber(token.to_string()))
+}
+
+fn parse_vec3<'a, I>(tokens: &mut I, record: &'static str) -> Result<Vec3, ObjErrorKind>
+where
+    I: Iterator<Item = &'a str>,
+{
+    let x = parse_float(tokens.next(), record)?;
+    let y = parse_float(tokens.next(), record)?;
+    let z = parse_float(tokens.next(), record)?;
+    Ok(Vec3::build(x, y, z))
+}
+
+#[derive(Clone, Copy)]
+struct MtlEntry {
+    diffuse: Vec3,
+    diffuse_map: Option<Texture>,
+    normal_map: Option<NormalMap>,
+    specular: Vec3,
+    emission: Vec3,
+    transmission: Option<Vec3>,
+    shininess: Float,
+    refraction_index: Float,
+    dissolve: Float,
+    illum: u32,
+}
+
+impl MtlEntry {
+    fn new() -> Self {
+        MtlEntry {
+            diffuse: Vec3::build(0.8, 0.8, 0.8),
+            diffuse_map: None,
+            normal_map: None,
+            specular: Vec3::zeros(),
+            emission: Vec3::zeros(),
+            transmission: None,
+            shininess: 0.,
+            refraction_index: 1.5,
+            dissolve: 1.,
+            illum: 2,
+        }
+    }
+
+    // mtl is a phong model so this is only ever an approximation of what the artist had in mind, a
+    // diffuse map replaces kd instead of multiplying it since exporters mostly leave kd at white
+    fn to_material(self) -> Material {
+        let diffuse = self.diffuse_map.unwrap_or(Texture::Solid(self.diffuse));
+        if !self.emission.near_zero() {
+            return Material::Source(Source::build(self.emission));
+        }
+        if self.dissolve < 1. || matches!(self.illum, 4 | 6 | 7 | 9) {
+            let albedo = self.transmission.unwrap_or(Vec3::build(1., 1., 1.));
+            return Material::Glass(Glass::build(albedo, self.refraction_index));
+        }
+        if matches!(self.illum, 3 | 5 | 8) {
+            let albedo = if self.specular.near_zero() { diffuse } else { Texture::Solid(self.specular) };
+            // the usual beckmann alpha for a phong exponent, which ggx's alpha is close enough to
+            let roughness = (2. / (self.shininess + 2.)).sqrt().sqrt();
+            return Material::Metal(Metal::build(albedo, roughness).with_normal_map(self.normal_map));
+        }
+
+        Material::Lambertian(Lambertian::build(diffuse).with_normal_map(self.normal_map))
+    }
+}
+
+// options like -s come before the file name, which is always last and relative to the mtl, -bm is
+// the only one that matters here and comes back alongside the texture
+fn parse_map<'a, I>(
+    tokens: &mut I,
+    record: &'static str,
+    mtl_path: &Path,
+    linear: bool,
+    images: &mut ImageTable,
+) -> Result<(Texture, Float), ObjErrorKind>
+where
+    I: Iterator<Item = &'a str>,
+{
+    let (mut name, mut multiplier) = (None, 1.);
+    while let Some(token) = tokens.next() {
+        match token {
+            "-bm" => multiplier = parse_float(tokens.next(), record)?,
+            _ => name = Some(token),
+        }
+    }
+
+    let name = name.ok_or(ObjErrorKind::MissingValue(record))?;
+    let image_path = mtl_path.parent().unwrap_or(Path::new("")).join(name);
+    let image =
+        images.load(&image_path, linear).map_err(|image_error| ObjErrorKind::Image(Box::new(image_error)))?;
+    Ok((Texture::Image(ImageTexture::build(image, Wrap::Repeat, Filter::Trilinear, 1.)), multiplier))
+}
+
+fn parse_mtl(path: &Path, images: &mut ImageTable) -> Result<Vec<(String, Material)>, ObjError> {
+    let file = File::open(path).map_err(|error| ObjError::build(path, 0, ObjErrorKind::Io(error)))?;
+    let mut entries: Vec<(String, MtlEntry)> = Vec::new();
+
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let number = number + 1;
+        let error = |kind| ObjError::build(path, number, kind);
+        let line = line.map_err(|io_error| error(ObjErrorKind::Io(io_error)))?;
+        let mut tokens = line.split_whitespace();
+        let Some(record) = tokens.next()
+        else {
+            continue;
+        };
+
+        if record == "newmtl" {
+            let name = tokens.next().ok_or_else(|| error(ObjErrorKind::MissingValue("newmtl")))?;
+            entries.push((name.to_string(), MtlEntry::new()));
+            continue;
+        }
+        // anything before the first newmtl has nowhere to go
+        let Some((_, entry)) = entries.last_mut()
+        else {
+            continue;
+        };
+
+        match record {
+            "Kd" => entry.diffuse = parse_vec3(&mut tokens, "Kd").map_err(error)?,
+            "Ks" => entry.specular = parse_vec3(&mut tokens, "Ks").map_err(error)?,
+            "Ke" => entry.emission = parse_vec3(&mut tokens, "Ke").map_err(error)?,
+            "Tf" => entry.transmission = Some(parse_vec3(&mut tokens, "Tf").map_err(error)?),
+            "Ns" => entry.shininess = parse_float(tokens.next(), "Ns").map_err(error)?,
+            "Ni" => entry.refraction_index = parse_float(tokens.next(), "Ni").map_err(error)?,
+            "d" => entry.dissolve = parse_float(tokens.next(), "d").map_err(error)?,
+            "Tr" => entry.dissolve = 1. - parse_float(tokens.next(), "Tr").map_err(error)?,
+            "map_Kd" => {
+                let (map, _) = parse_map(&mut tokens, "map_Kd", path, false, images).map_err(error)?;
+                entry.diffuse_map = Some(map);
+            }
+            "norm" => {
+                let (map, _) = parse_map(&mut tokens, "norm", path, true, images).map_err(error)?;
+                entry.normal_map = Some(NormalMap::Tangent { map, strength: 1. });
+            }
+            // heights, even though plenty of exporters put tangent normal maps here as well
+            "bump" | "map_Bump" => {
+                let (height, strength) = parse_map(&mut tokens, "bump", path, true, images).map_err(error)?;
+                entry.normal_map = Some(NormalMap::Bump { height, strength });
+            }
+            "illum" => {
+                let value = tokens.next().ok_or_else(|| error(ObjErrorKind::MissingValue("illum")))?;
+                entry.illum =
+                    value.parse().map_err(|_| error(ObjErrorKind::InvalidNumber(value.to_string())))?;
+            }
+            _ => {}
+        }
+    }
+
+    Ok(entries.into_iter().map(|(name, entry)| (name, entry.to_material())).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SQUARE: &str = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\n";
+
+    fn parse(source: &str) -> Result<ObjModel, ObjError> {
+        ObjModel::parse(source.as_bytes(), Path::new("inline.obj"), &mut ImageTable::new())
+    }
+
+    fn positions(face: &ObjFace) -> [usize; 3] {
+        face.vertices.map(|vertex| vertex.position)
+    }
+
+    #[test]
+    fn negative_indices_count_back_from_the_latest_vertex() {
+        let model = parse(&format!("{}f -4 -3 -2\nv 2 2 0\nf -1 -2 -3\n", SQUARE)).unwrap();
+        assert_eq!(positions(&model.faces[0]), [0, 1, 2]);
+        assert_eq!(positions(&model.faces[1]), [4, 3, 2]);
+    }
+
+    #[test]
+    fn polygons_are_fan_triangulated() {
+        let model = parse(&format!("{}v 0.5 2 0\nf 1 2 3 5 4\n", SQUARE)).unwrap();
+        let faces: Vec<[usize; 3]> = model.faces.iter().map(positions).collect();
+        assert_eq!(faces, [[0, 1, 2], [0, 2, 4], [0, 4, 3]]);
+    }
+
+    #[test]
+    fn uvs_and_normals_can_each_be_left_out() {
+        let source =
+            format!("{}vt 0 0\nvt 1 0\nvn 0 0 1\nf 1//1 2//1 3//1\nf 1/1 2/2 3/2\nf 1/2/1 2 3\n", SQUARE);
+        let model = parse(&source).unwrap();
+        let vertices = |face: usize| model.faces[face].vertices.map(|vertex| (vertex.uv, vertex.normal));
+        assert_eq!(vertices(0), [(None, Some(0)); 3]);
+        assert_eq!(vertices(1), [(Some(0), None), (Some(1), None), (Some(1), None)]);
+        assert_eq!(vertices(2), [(Some(1), Some(0)), (None, None), (None, None)]);
+    }
+
+    #[test]
+    fn errors_point_at_their_line() {
+        let cases = [
+            (format!("{}\nf 1 2 5\n", SQUARE), 6),
+            (format!("{}f 1 2 0\n", SQUARE), 5),
+            (format!("{}vt 0 0\nf 1/2 2/1 3/1\n", SQUARE), 6),
+            ("v 0 zero 0\n".to_string(), 1),
+            (format!("{}f 1/x 2 3\n", SQUARE), 5),
+            (format!("{}# two\nf 1 2\n", SQUARE), 6),
+        ];
+        for (source, line) in cases {
+            let error = parse(&source).err().expect("malformed obj parsed");
+            assert_eq!(error.line, line, "{}", error);
+        }
+
+        let kind = |source: String| parse(&source).err().unwrap().kind;
+        assert!(matches!(kind(format!("{}f 1 2 5\n", SQUARE)), ObjErrorKind::IndexOutOfRange(5)));
+        assert!(matches!(kind(format!("{}f 1 2 -5\n", SQUARE)), ObjErrorKind::IndexOutOfRange(-5)));
+        assert!(matches!(kind(format!("{}f 1 2 0\n", SQUARE)), ObjErrorKind::IndexOutOfRange(0)));
+        assert!(
+            matches!(kind("v 0 zero 0\n".to_string()), ObjErrorKind::InvalidNumber(value) if value == "zero")
+        );
+        assert!(
+            matches!(kind(format!("{}f 1/x 2 3\n", SQUARE)), ObjErrorKind::InvalidIndex(value) if value == "x")
+        );
+        assert!(matches!(kind(format!("{}f 1 2\n", SQUARE)), ObjErrorKind::TooFewVertices(2)));
+        assert!(matches!(kind("v 0 0\n".to_string()), ObjErrorKind::MissingValue("v")));
+    }
+}
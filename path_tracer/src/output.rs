@@ -0,0 +1,409 @@
+use std::io;
+use std::io::BufWriter;
+use std::io::Write;
+use std::path::Path;
+
+use png::BitDepth as PngBitDepth;
+use png::ColorType;
+use png::Encoder;
+use png::SrgbRenderingIntent;
+
+use crate::hdr_buffer::HdrBuffer;
+use crate::tonemap::Tonemap;
+use crate::utils::Float;
+use crate::vector::Vec3;
+
+// ppm and png are display referred and go through the tonemap, pfm and exr keep the linear
+// radiance as is
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Ppm,
+    Png,
+    Pfm,
+    Exr,
+}
+
+impl Format {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "ppm" => Some(Format::Ppm),
+            "png" => Some(Format::Png),
+            "pfm" => Some(Format::Pfm),
+            "exr" => Some(Format::Exr),
+            _ => None,
+        }
+    }
+
+    pub fn from_path(path: &Path) -> Option<Self> {
+        Format::from_name(path.extension()?.to_str()?)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Format::Ppm => "ppm",
+            Format::Png => "png",
+            Format::Pfm => "pfm",
+            Format::Exr => "exr",
+        }
+    }
+
+    pub fn default_depth(&self) -> BitDepth {
+        match self {
+            Format::Ppm | Format::Png => BitDepth::Eight,
+            Format::Exr => BitDepth::Sixteen,
+            Format::Pfm => BitDepth::ThirtyTwo,
+        }
+    }
+
+    pub fn supports_depth(&self, depth: BitDepth) -> bool {
+        match self {
+            Format::Ppm | Format::Png => matches!(depth, BitDepth::Eight | BitDepth::Sixteen),
+            Format::Exr => matches!(depth, BitDepth::Sixteen | BitDepth::ThirtyTwo),
+            Format::Pfm => depth == BitDepth::ThirtyTwo,
+        }
+    }
+}
+
+// integers for ppm and png, for exr sixteen means half floats and thirty two full floats
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BitDepth {
+    Eight,
+    Sixteen,
+    ThirtyTwo,
+}
+
+impl BitDepth {
+    pub fn from_bits(bits: u32) -> Option<Self> {
+        match bits {
+            8 => Some(BitDepth::Eight),
+            16 => Some(BitDepth::Sixteen),
+            32 => Some(BitDepth::ThirtyTwo),
+            _ => None,
+        }
+    }
+
+    pub fn bits(&self) -> u32 {
+        match self {
+            BitDepth::Eight => 8,
+            BitDepth::Sixteen => 16,
+            BitDepth::ThirtyTwo => 32,
+        }
+    }
+
+    // write only lets the integer formats through with 8 or 16 bits
+    fn max_value(&self) -> u32 {
+        match self {
+            BitDepth::Eight => 255,
+            BitDepth::Sixteen | BitDepth::ThirtyTwo => 65535,
+        }
+    }
+}
+
+// an extra image stored next to the render under its own name, such as albedo or normals for an
+// external denoiser, only exr has anywhere to put them
+pub struct Layer {
+    pub name: String,
+    pub pixels: Vec<Vec3>,
+}
+
+pub fn write<W: Write>(
+    buffer: &HdrBuffer,
+    layers: &[Layer],
+    writer: W,
+    format: Format,
+    depth: BitDepth,
+    tonemap: &Tonemap,
+) -> io::Result<()> {
+    if !format.supports_depth(depth) {
+        let message = format!("{} output can't be written with {} bits", format.name(), depth.bits());
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+    }
+    if !layers.is_empty() && format != Format::Exr {
+        let message = format!("{} output can't hold extra layers", format.name());
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+    }
+
+    match format {
+        Format::Ppm => write_ppm(buffer, writer, depth, tonemap),
+        Format::Png => write_png(buffer, writer, depth, tonemap),
+        Format::Pfm => write_pfm(buffer, writer),
+        Format::Exr => write_exr(buffer, layers, writer, depth),
+    }
+}
+
+// ascii p3, kept because it needs nothing to read back, but the files get big
+fn write_ppm<W: Write>(buffer: &HdrBuffer, writer: W, depth: BitDepth, tonemap: &Tonemap) -> io::Result<()> {
+    let mut writer = BufWriter::with_capacity(buffer.width * buffer.height * 12, writer);
+    writeln!(writer, "P3\n{} {}\n{}", buffer.width, buffer.height, depth.max_value())?;
+    for y in 0..buffer.height {
+        for x in 0..buffer.width {
+            let [red, green, blue] = encode(buffer.color(x, y), depth, tonemap);
+            write!(writer, "{} {} {} ", red, green, blue)?;
+        }
+        writeln!(writer)?;
+    }
+    writer.flush()
+}
+
+fn write_png<W: Write>(buffer: &HdrBuffer, writer: W, depth: BitDepth, tonemap: &Tonemap) -> io::Result<()> {
+    let mut encoder = Encoder::new(writer, buffer.width as u32, buffer.height as u32);
+    encoder.set_color(ColorType::Rgb);
+    encoder.set_depth(match depth {
+        BitDepth::Eight => PngBitDepth::Eight,
+        BitDepth::Sixteen | BitDepth::ThirtyTwo => PngBitDepth::Sixteen,
+    });
+    encoder.set_source_srgb(SrgbRenderingIntent::Perceptual);
+
+    let mut data = Vec::with_capacity(buffer.width * buffer.height * 6);
+    for y in 0..buffer.height {
+        for x in 0..buffer.width {
+            for channel in encode(buffer.color(x, y), depth, tonemap) {
+                match depth {
+                    BitDepth::Eight => data.push(channel as u8),
+                    // png stores 16 bit samples big endian
+                    BitDepth::Sixteen | BitDepth::ThirtyTwo => data.extend_from_slice(&channel.to_be_bytes()),
+                }
+            }
+        }
+    }
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&data)?;
+    writer.finish()?;
+
+    Ok(())
+}
+
+// little endian rgb floats with the rows stored bottom up
+fn write_pfm<W: Write>(buffer: &HdrBuffer, writer: W) -> io::Result<()> {
+    let mut writer = BufWriter::new(writer);
+    // a negative scale marks the data as little endian
+    write!(writer, "PF\n{} {}\n-1.0\n", buffer.width, buffer.height)?;
+    for y in (0..buffer.height).rev() {
+        for x in 0..buffer.width {
+            let color = buffer.color(x, y);
+            for channel in [color.x, color.y, color.z] {
+                writer.write_all(&channel.to_le_bytes())?;
+            }
+        }
+    }
+    writer.flush()
+}
+
+const EXR_MAGIC: u32 = 20000630;
+const EXR_HALF: i32 = 1;
+const EXR_FLOAT: i32 = 2;
+
+// single part, uncompressed scanlines with one line per block, which every reader supports
+fn write_exr<W: Write>(
+    buffer: &HdrBuffer,
+    layers: &[Layer],
+    mut writer: W,
+    depth: BitDepth,
+) -> io::Result<()> {
+    let (width, height) = (buffer.width, buffer.height);
+    let (pixel_type, sample_size) = match depth {
+        BitDepth::ThirtyTwo => (EXR_FLOAT, 4),
+        _ => (EXR_HALF, 2),
+    };
+
+    // the render goes in the unprefixed r, g and b channels, layers become `name.R` and so on
+    let beauty: Vec<Vec3> = buffer.pixels.iter().map(|pixel| pixel.color()).collect();
+    let mut channels: Vec<(String, &[Vec3], usize)> = Vec::new();
+    let planes = std::iter::once(("", beauty.as_slice()))
+        .chain(layers.iter().map(|layer| (layer.name.as_str(), layer.pixels.as_slice())));
+    for (prefix, pixels) in planes {
+        for (axis, suffix) in ["R", "G", "B"].iter().enumerate() {
+            let name = if prefix.is_empty() { suffix.to_string() } else { format!("{}.{}", prefix, suffix) };
+            channels.push((name, pixels, axis));
+        }
+    }
+    // readers expect the channel list, and the data in each block, sorted by name
+    channels.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut channel_list = Vec::new();
+    for (name, _, _) in &channels {
+        channel_list.extend_from_slice(name.as_bytes());
+        channel_list.push(0);
+        channel_list.extend_from_slice(&pixel_type.to_le_bytes());
+        // linear flag and three reserved bytes, then x and y sampling
+        channel_list.extend_from_slice(&[0; 4]);
+        channel_list.extend_from_slice(&1i32.to_le_bytes());
+        channel_list.extend_from_slice(&1i32.to_le_bytes());
+    }
+    channel_list.push(0);
+
+    let mut window = Vec::new();
+    for value in [0, 0, width as i32 - 1, height as i32 - 1] {
+        window.extend_from_slice(&value.to_le_bytes());
+    }
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&EXR_MAGIC.to_le_bytes());
+    header.extend_from_slice(&2u32.to_le_bytes());
+    exr_attribute(&mut header, "channels", "chlist", &channel_list);
+    exr_attribute(&mut header, "compression", "compression", &[0]);
+    exr_attribute(&mut header, "dataWindow", "box2i", &window);
+    exr_attribute(&mut header, "displayWindow", "box2i", &window);
+    exr_attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+    exr_attribute(&mut header, "pixelAspectRatio", "float", &1f32.to_le_bytes());
+    exr_attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+    exr_attribute(&mut header, "screenWindowWidth", "float", &1f32.to_le_bytes());
+    header.push(0);
+
+    // the offset table points at every block, and each block is its y and size followed by the
+    // line of every channel in turn
+    let line_size = width * channels.len() * sample_size;
+    let block_size = 8 + line_size;
+    let first_block = header.len() + height * 8;
+    for y in 0..height {
+        header.extend_from_slice(&((first_block + y * block_size) as u64).to_le_bytes());
+    }
+    writer.write_all(&header)?;
+
+    let mut block = Vec::with_capacity(block_size);
+    for y in 0..height {
+        block.clear();
+        block.extend_from_slice(&(y as i32).to_le_bytes());
+        block.extend_from_slice(&(line_size as i32).to_le_bytes());
+        for (_, pixels, axis) in &channels {
+            for pixel in &pixels[y * width..(y + 1) * width] {
+                let value = pixel.axis(*axis);
+                match depth {
+                    BitDepth::ThirtyTwo => block.extend_from_slice(&value.to_le_bytes()),
+                    _ => block.extend_from_slice(&half_bits(value).to_le_bytes()),
+                }
+            }
+        }
+        writer.write_all(&block)?;
+    }
+    writer.flush()
+}
+
+fn exr_attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(kind.as_bytes());
+    header.push(0);
+    header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    header.extend_from_slice(value);
+}
+
+// ieee half precision with round to nearest even, too large values become infinity and too small
+// ones go through the subnormals down to zero
+fn half_bits(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+
+    if exponent == 0xff {
+        let nan = if mantissa != 0 { 0x200 } else { 0 };
+        return sign | 0x7c00 | nan;
+    }
+
+    let exponent = exponent - 127 + 15;
+    if exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+    let (half, remainder, halfway) = if exponent <= 0 {
+        if exponent < -10 {
+            return sign;
+        }
+        let mantissa = mantissa | 0x80_0000;
+        let shift = (14 - exponent) as u32;
+        (mantissa >> shift, mantissa & ((1 << shift) - 1), 1 << (shift - 1))
+    }
+    else {
+        (((exponent as u32) << 10) | (mantissa >> 13), mantissa & 0x1fff, 0x1000)
+    };
+
+    // a carry out of the mantissa lands in the exponent, which is exactly the right rounding
+    let round_up = remainder > halfway || (remainder == halfway && half & 1 == 1);
+    sign | (half + round_up as u32) as u16
+}
+
+fn encode(color: Vec3, depth: BitDepth, tonemap: &Tonemap) -> [u16; 3] {
+    let color = tonemap.display(color);
+    let scale = depth.max_value() as Float + 0.999;
+    [color.x, color.y, color.z].map(|channel| (channel * scale) as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::fs::File;
+
+    use super::*;
+    use crate::image::Image;
+    use crate::tonemap::srgb_encode;
+
+    #[test]
+    fn half_bits_matches_known_encodings() {
+        let cases = [
+            (0., 0x0000),
+            (-0., 0x8000),
+            (1., 0x3c00),
+            (-1., 0xbc00),
+            (65504., 0x7bff),
+            // past halfway to the next step above the largest half, which would be infinity
+            (65520., 0x7c00),
+            (1e6, 0x7c00),
+            (f32::INFINITY, 0x7c00),
+            (f32::NEG_INFINITY, 0xfc00),
+            (2f32.powi(-14), 0x0400),
+            (2f32.powi(-24), 0x0001),
+            (-(2f32.powi(-24)), 0x8001),
+            (1023. * 2f32.powi(-24), 0x03ff),
+            // exact ties go to the even neighbour, above and below the subnormal range
+            (2f32.powi(-25), 0x0000),
+            (3. * 2f32.powi(-25), 0x0002),
+            (1. + 2f32.powi(-11), 0x3c00),
+            (1. + 3. * 2f32.powi(-11), 0x3c02),
+            (1. + 2f32.powi(-11) + 2f32.powi(-20), 0x3c01),
+            (1e-10, 0x0000),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(half_bits(value), expected, "{:e}", value);
+        }
+    }
+
+    #[test]
+    fn half_bits_keeps_nan_a_nan() {
+        for nan in [f32::NAN, -f32::NAN] {
+            let bits = half_bits(nan);
+            assert_eq!(bits & 0x7c00, 0x7c00);
+            assert_ne!(bits & 0x03ff, 0);
+        }
+    }
+
+    #[test]
+    fn png_reads_back_what_was_written() {
+        let mut buffer = HdrBuffer::build(3, 4);
+        for (index, pixel) in buffer.pixels.iter_mut().enumerate() {
+            let value = index as Float / 12.;
+            pixel.add(Vec3::build(value, 1. - value, value * value), 1);
+        }
+        let tonemap = Tonemap::default();
+
+        for (depth, step) in [(BitDepth::Eight, 1. / 255.), (BitDepth::Sixteen, 1. / 65535.)] {
+            let name = format!("path_tracer_{}_{}.png", depth.bits(), std::process::id());
+            let path = std::env::temp_dir().join(name);
+            write(&buffer, &[], File::create(&path).unwrap(), Format::Png, depth, &tonemap).unwrap();
+            let image = Image::load(&path, false).unwrap();
+            fs::remove_file(&path).unwrap();
+
+            assert_eq!((image.width, image.height), (4, 3));
+            for y in 0..3 {
+                for x in 0..4 {
+                    // the loader hands back linear values, so encode them again to compare codes
+                    let read = image.texel(x, y);
+                    let read = Vec3::build(srgb_encode(read.x), srgb_encode(read.y), srgb_encode(read.z));
+                    let difference = read - tonemap.display(buffer.color(x, y));
+                    let error = difference.x.abs().max(difference.y.abs()).max(difference.z.abs());
+                    assert!(error <= step * 1.01, "{} bits at {}, {}", depth.bits(), x, y);
+                }
+            }
+        }
+    }
+}
@@ -1,31 +1,56 @@
+use crate::geometry::Aabb;
+use crate::image::ImageTable;
 use crate::materials::Material;
 use crate::utils::Float;
 use crate::utils::Interval;
 use crate::utils::INFIN;
-use crate::Vec3;
+use crate::utils::SMALL;
+use crate::vector::Vec3;
 
 pub trait Hittable {
     fn hit(&self, ray: &Ray, record: &mut HitRecord) -> bool;
 }
 
+pub trait Bounded {
+    fn bounding_box(&self) -> Aabb;
+}
+
+// evaluate gives the bsdf already multiplied by the cosine term and pdf is over solid angle, both
+// zero for specular materials since a delta can't be evaluated for an arbitrary direction
 pub trait Scatter {
     fn scatter(&self, record: &HitRecord, scatter: &mut ScatterRecord) -> bool;
+    fn evaluate(&self, record: &HitRecord, direction: &Vec3) -> Vec3;
+    fn pdf(&self, record: &HitRecord, direction: &Vec3) -> Float;
 }
 
-#[derive(Default)]
+// width and spread describe a cone around the ray, how wide it is at the origin and how much wider
+// it gets per unit of distance, which is all texture filtering knows about a pixel's footprint
+#[derive(Clone, Copy, Default)]
 pub struct Ray {
     pub origin: Vec3,
     pub direction: Vec3,
+    pub width: Float,
+    pub spread: Float,
 }
 
 impl Ray {
     pub const fn build(origin: Vec3, direction: Vec3) -> Self {
-        Ray { origin, direction }
+        Ray { origin, direction, width: 0., spread: 0. }
+    }
+
+    pub const fn with_cone(mut self, width: Float, spread: Float) -> Self {
+        self.width = width;
+        self.spread = spread;
+        self
     }
 
     pub fn at_time(&self, time: Float) -> Vec3 {
         self.origin + self.direction * time
     }
+
+    pub fn inverse_direction(&self) -> Vec3 {
+        Vec3::build(1. / self.direction.x, 1. / self.direction.y, 1. / self.direction.z)
+    }
 }
 
 #[derive(Clone, Copy, Default)]
@@ -33,10 +58,24 @@ pub struct HitRecord {
     pub point: Vec3,
     pub ray_in: Vec3,
     pub normal: Vec3,
+    pub shading_normal: Vec3,
     pub intersection_time: Float,
     pub interval: Interval,
     pub material: Material,
     pub front_face: bool,
+    // texture coordinates, barycentric for triangles that come without any, how many uv units one
+    // unit of surface covers and the ray cone's width at the hit
+    pub uv: (Float, Float),
+    pub uv_density: Float,
+    pub footprint: Float,
+    // dp/du and dp/dv, not normalized, which is what normal and bump maps orient themselves by
+    pub tangent: Vec3,
+    pub bitangent: Vec3,
+    // which scene object was hit, and which face of it for meshes
+    pub object: usize,
+    pub face: usize,
+    // the material's texture at the hit, only filled in by resolve_textures
+    pub albedo: Vec3,
 }
 
 impl HitRecord {
@@ -44,6 +83,7 @@ impl HitRecord {
         HitRecord { intersection_time: -INFIN, ..Default::default() }
     }
 
+    // also resets the shading normal, so call this before set_shading_normal
     pub fn set_face_normal(&mut self, outward_normal: &Vec3) {
         self.front_face = self.ray_in.inner_product(outward_normal) < 0.;
         if self.front_face {
@@ -52,6 +92,33 @@ impl HitRecord {
         else {
             self.normal = -(*outward_normal);
         }
+        self.shading_normal = self.normal;
+    }
+
+    // flipped to the same side as the geometric normal so front_face still holds for it
+    pub fn set_shading_normal(&mut self, outward_normal: &Vec3) {
+        if self.front_face {
+            self.shading_normal = *outward_normal;
+        }
+        else {
+            self.shading_normal = -(*outward_normal);
+        }
+    }
+
+    // lets the material's normal map, if it has one, tilt the shading normal and then looks up
+    // the albedo, which is all materials need from their textures
+    pub fn resolve_textures(&mut self, images: &ImageTable) {
+        if let Some(normal_map) = self.material.normal_map() {
+            normal_map.apply(self, images);
+        }
+        self.albedo = self.material.texture().value(self, images);
+    }
+
+    // nudges the origin off the surface along the geometric normal, shading normals can point
+    // anywhere on a coarse mesh so they can't be trusted for this
+    pub fn spawn_ray(&self, direction: Vec3) -> Ray {
+        let offset = if direction.inner_product(&self.normal) > 0. { self.normal } else { -self.normal };
+        Ray::build(self.point + offset * SMALL, direction)
     }
 }
 
@@ -59,10 +126,12 @@ impl HitRecord {
 pub struct ScatterRecord {
     pub attenuation: Vec3,
     pub scattered: Ray,
+    pub pdf: Float,
+    pub specular: bool,
 }
 
 impl ScatterRecord {
     pub fn new() -> Self {
-        ScatterRecord { attenuation: Vec3::zeros(), scattered: Ray::default() }
+        ScatterRecord { attenuation: Vec3::zeros(), scattered: Ray::default(), pdf: 0., specular: false }
     }
 }
@@ -1,112 +1,27 @@
 use minifb::Key;
+use minifb::KeyRepeat;
 use minifb::Window;
 
 use crate::camera::Camera;
+use crate::hdr_buffer::HdrBuffer;
 use crate::scene::Scene;
-use crate::utils::packed_color;
-use crate::utils::unpack_color;
 use crate::utils::Float;
-use crate::utils::Int;
-use crate::utils::SMALL;
-use crate::vector::Vec3;
 
-#[derive(Clone)]
-pub struct Buffer {
-    pub pixels: Vec<u32>,
-    pub height: usize,
-    pub width: usize,
-}
-
-impl Buffer {
-    pub fn build(height: usize, width: usize) -> Buffer {
-        Buffer { height, width, pixels: vec![0; width * height] }
-    }
-
-    pub fn write_pixel(&mut self, x: usize, y: usize, color: Vec3) {
-        self.pixels[y * self.width + x] = packed_color(color);
-    }
-
-    // quite terrible and needs to be refactored but it is working well now
-    pub fn bilateral_denoise(&mut self, iters: Int) {
-        let radius = 3;
-        let mut sigma_spatial = 2.0;
-        let mut sigma_color = 0.1;
-
-        (0..iters).for_each(|_| {
-            let mut guidance_buffer = self.clone();
-            guidance_buffer.box_blur(radius);
-            let mut result = self.pixels.clone();
-
-            for y in radius..self.height - radius {
-                for x in radius..self.width - radius {
-                    let center_idx = y * self.width + x;
-
-                    let center_color = unpack_color(guidance_buffer.pixels[center_idx]);
-                    let mut color = Vec3::zeros();
-                    let mut weight_sum = 0.;
+const TITLE: &str = "cpu path traced";
+const MOVEMENT_KEYS: [Key; 10] =
+    [Key::W, Key::S, Key::A, Key::D, Key::Q, Key::E, Key::R, Key::F, Key::Key1, Key::Key2];
 
-                    for dy in -(radius as isize)..=radius as isize {
-                        for dx in -(radius as isize)..=radius as isize {
-                            let ny = (y as isize + dy) as usize;
-                            let nx = (x as isize + dx) as usize;
-                            let idx = ny * self.width + nx;
-
-                            let neighbor_color_input = unpack_color(self.pixels[idx]);
-                            if neighbor_color_input.near_zero() {
-                                continue;
-                            }
-                            let neighbor_color_guidance = unpack_color(guidance_buffer.pixels[idx]);
-
-                            let spatial_dist = (dx * dx + dy * dy) as Float;
-                            let color_dist = (neighbor_color_guidance - center_color)
-                                .inner_product(&(neighbor_color_guidance - center_color));
-
-                            let spatial_weight = (-spatial_dist / (2. * sigma_spatial * sigma_spatial)).exp();
-                            let color_weight = (-color_dist / (2. * sigma_color * sigma_color)).exp();
-                            let weight = spatial_weight * color_weight;
-
-                            color += neighbor_color_input * weight;
-                            weight_sum += weight;
-                        }
-                    }
-                    let filtered_color = color / weight_sum.max(SMALL);
-                    result[center_idx] = packed_color(filtered_color);
-                }
-            }
-
-            self.pixels = result;
-            sigma_color /= 1.2;
-            sigma_spatial *= 1.2;
-        });
-    }
+// how far one press of the exposure keys moves, in stops
+const EXPOSURE_STEP: Float = 0.5;
 
-    pub fn box_blur(&mut self, radius: usize) {
-        let mut result = self.pixels.clone();
-        for y in radius..self.height - radius {
-            for x in radius..self.width - radius {
-                let mut sum = Vec3::zeros();
-                let mut count = 0.;
-                for dy in -(radius as isize)..=(radius as isize) {
-                    for dx in -(radius as isize)..=(radius as isize) {
-                        let ny = (y as isize + dy) as usize;
-                        let nx = (x as isize + dx) as usize;
-                        let idx = ny * self.width + nx;
-                        sum += unpack_color(self.pixels[idx]);
-                        count += 1.;
-                    }
-                }
-
-                let idx = y * self.width + x;
-                result[idx] = packed_color(sum / count);
-            }
-        }
-
-        self.pixels = result;
-    }
-}
-
-pub fn run_application(camera: &mut Camera, buffer: &mut Buffer, scene: &Scene, window: &mut Window) {
+// frames keep adding into the buffer while the camera is still so the preview converges, any
+// movement throws the accumulated samples away, exposure only changes how they are displayed
+pub fn run_application(camera: &mut Camera, buffer: &mut HdrBuffer, scene: &Scene, window: &mut Window) {
+    let mut frame = vec![0; buffer.width * buffer.height];
+    let mut samples = 0;
+    buffer.clear();
     while !window.is_key_down(Key::Escape) && window.is_open() {
+        let moved = MOVEMENT_KEYS.iter().any(|key| window.is_key_down(*key));
         if window.is_key_down(Key::W) {
             camera.position -= camera.front * camera.move_speed;
         }
@@ -137,15 +52,45 @@ pub fn run_application(camera: &mut Camera, buffer: &mut Buffer, scene: &Scene,
         if window.is_key_down(Key::Key2) {
             camera.position -= camera.world_up * camera.move_speed;
         }
-        camera.set_viewport();
+        if window.is_key_pressed(Key::Equal, KeyRepeat::Yes) {
+            camera.tonemap.exposure += EXPOSURE_STEP;
+            println!("exposure: {}", camera.tonemap.exposure);
+        }
+        if window.is_key_pressed(Key::Minus, KeyRepeat::Yes) {
+            camera.tonemap.exposure -= EXPOSURE_STEP;
+            println!("exposure: {}", camera.tonemap.exposure);
+        }
+        if window.is_key_pressed(Key::P, KeyRepeat::No) {
+            println!("{}", camera.pose());
+        }
+        if window.is_key_pressed(Key::Space, KeyRepeat::No) {
+            match camera.autofocus(scene) {
+                Some(distance) => {
+                    println!("focus distance: {}", distance);
+                    buffer.clear();
+                    samples = 0;
+                }
+                None => println!("nothing under the center to focus on"),
+            }
+        }
+        if moved {
+            camera.set_viewport();
+            buffer.clear();
+            samples = 0;
+            println!(
+                "position: {:?} front: {:?}, yaw: {}, pitch: {}",
+                camera.position, camera.front, camera.yaw, camera.pitch
+            );
+        }
+
         camera.render_to_buffer_par(buffer, scene);
-        buffer.bilateral_denoise(camera.denoise_iters);
-        window
-            .update_with_buffer(&buffer.pixels, buffer.width, buffer.height)
-            .expect("error updating window");
-        println!(
-            "position: {:?} front: {:?}, yaw: {}, pitch: {}",
-            camera.position, camera.front, camera.yaw, camera.pitch
-        );
+        samples += camera.samples;
+
+        // denoise a copy so the filtered result never feeds back into the running mean
+        let mut display = buffer.clone();
+        display.bilateral_denoise(camera.denoise_iters);
+        display.write_packed(&mut frame, &camera.tonemap);
+        window.set_title(&format!("{} - {} samples", TITLE, samples));
+        window.update_with_buffer(&frame, buffer.width, buffer.height).expect("error updating window");
     }
 }
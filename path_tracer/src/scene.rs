@@ -1,54 +1,283 @@
+use std::sync::OnceLock;
+
+use crate::bvh::Bvh;
+use crate::geometry::Aabb;
 use crate::geometry::Geometry;
+use crate::geometry::Mesh;
 use crate::geometry::Sphere;
 use crate::geometry::Triangle;
+use crate::image::ImageTable;
+use crate::materials::Material;
+use crate::obj::ObjModel;
+use crate::ray_hit::Bounded;
 use crate::ray_hit::HitRecord;
 use crate::ray_hit::Hittable;
 use crate::ray_hit::Ray;
 use crate::ray_hit::ScatterRecord;
+use crate::utils::power_heuristic;
+use crate::utils::random;
 use crate::utils::sky_gradient;
+use crate::utils::Float;
 use crate::utils::Int;
+use crate::utils::SMALL;
 use crate::vector::Vec3;
 
+// roulette never lets a path survive for certain, otherwise one that keeps all its energy, like
+// inside a closed white room, would bounce forever
+const MAX_SURVIVAL: Float = 0.95;
+
+// an emissive primitive, face picks the triangle out of a mesh and is 0 for everything else
+struct Light {
+    object: usize,
+    face: usize,
+    emission: Vec3,
+}
+
+#[derive(Clone, Copy)]
+struct Bounce {
+    origin: Vec3,
+    pdf: Float,
+}
+
+// everything worked out from the primitives once they're all in
+struct Built {
+    bvh: Bvh,
+    lights: Vec<Light>,
+}
+
+// rays that escape see the background, or the sky gradient when there isn't one, image textures
+// on the scene's materials point into its image table
 pub struct Scene {
     hittables: Vec<Geometry>,
+    built: OnceLock<Built>,
+    background: Option<Vec3>,
+    images: ImageTable,
+    light_sampling: bool,
 }
 
 impl Scene {
     pub fn new() -> Self {
-        Scene { hittables: Vec::new() }
+        Scene {
+            hittables: Vec::new(),
+            built: OnceLock::new(),
+            background: None,
+            images: ImageTable::new(),
+            light_sampling: true,
+        }
+    }
+
+    pub fn set_background(&mut self, color: Vec3) {
+        self.background = Some(color);
+    }
+
+    // with it off emitters are only found by following the bsdf, much noisier but converging to
+    // the same image, which is what makes it worth keeping around as a reference
+    pub fn set_light_sampling(&mut self, enabled: bool) {
+        self.light_sampling = enabled;
+    }
+
+    pub fn images_mut(&mut self) -> &mut ImageTable {
+        &mut self.images
     }
 
     pub fn add_sphere(&mut self, sphere: Sphere) {
         self.hittables.push(Geometry::Sphere(sphere));
+        self.invalidate();
     }
 
     pub fn add_triangle(&mut self, triangle: Triangle) {
         self.hittables.push(Geometry::Triangle(triangle));
+        self.invalidate();
+    }
+
+    pub fn add_mesh(&mut self, mesh: Mesh) {
+        self.hittables.push(Geometry::Mesh(mesh));
+        self.invalidate();
+    }
+
+    // the bvh and light list only cover what was in the scene when it was last built
+    fn invalidate(&mut self) {
+        self.built = OnceLock::new();
+    }
+
+    // faces without a usemtl get the fallback material
+    pub fn add_obj(&mut self, model: &ObjModel, fallback: Material) {
+        self.add_mesh(Mesh::from_obj(model, fallback));
+    }
+
+    // the first ray traced builds the scene anyway, calling this once every primitive is in just
+    // keeps that cost out of the render, adding more afterwards means building again
+    pub fn build(&self) {
+        self.built();
+    }
+
+    fn built(&self) -> &Built {
+        self.built.get_or_init(|| {
+            let bounds: Vec<Aabb> = self.hittables.iter().map(|hittable| hittable.bounding_box()).collect();
+            Built { bvh: Bvh::build(&bounds), lights: self.gather_lights() }
+        })
     }
 
-    pub fn get_color(&self, ray: &Ray, depth: Int) -> Vec3 {
-        if depth.is_negative() {
+    fn gather_lights(&self) -> Vec<Light> {
+        let mut lights = Vec::new();
+        for (object, hittable) in self.hittables.iter().enumerate() {
+            match hittable {
+                Geometry::Sphere(sphere) => {
+                    lights.push(Light { object, face: 0, emission: sphere.material.emission() });
+                }
+                Geometry::Triangle(triangle) => {
+                    lights.push(Light { object, face: 0, emission: triangle.material().emission() });
+                }
+                Geometry::Mesh(mesh) => {
+                    for face in 0..mesh.face_count() {
+                        lights.push(Light { object, face, emission: mesh.face_material(face).emission() });
+                    }
+                }
+            }
+        }
+        lights.retain(|light| !light.emission.near_zero());
+        lights
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        self.hittables.iter().fold(Aabb::empty(), |bounds, hittable| bounds.union(&hittable.bounding_box()))
+    }
+
+    // max_depth is a hard cap for previews, past roulette_depth paths are ended at random in
+    // proportion to how little they can still contribute, which stays unbiased
+    pub fn get_color(&self, ray: &Ray, max_depth: Option<Int>, roulette_depth: Int) -> Vec3 {
+        let mut color = Vec3::zeros();
+        let mut throughput = Vec3::build(1., 1., 1.);
+        let mut ray = *ray;
+        // the non specular scatter that produced the current ray, if any, so emitters it runs into
+        // can be weighted against the light sample already taken from the same point
+        let mut bounce: Option<Bounce> = None;
+        let mut depth = 0;
+
+        while max_depth.is_none_or(|max_depth| depth <= max_depth) {
+            let mut record = HitRecord::new();
+            if !self.hit(&ray, &mut record) {
+                color += throughput.mul_component(&self.background.unwrap_or_else(|| sky_gradient(&ray)));
+                break;
+            }
+            record.resolve_textures(&self.images);
+
+            let mut scatter = ScatterRecord::new();
+            if !record.material.scatter(&record, &mut scatter) {
+                let weight = match bounce {
+                    Some(bounce) => power_heuristic(bounce.pdf, self.light_pdf(&record, &bounce.origin)),
+                    None => 1.,
+                };
+                color += throughput.mul_component(&scatter.attenuation) * weight;
+                break;
+            }
+
+            if scatter.specular || !self.light_sampling {
+                bounce = None;
+            }
+            else {
+                color += throughput.mul_component(&self.sample_direct(&record));
+                bounce = Some(Bounce { origin: record.point, pdf: scatter.pdf });
+            }
+            throughput = throughput.mul_component(&scatter.attenuation);
+
+            if depth >= roulette_depth {
+                let survival = throughput.max_component().min(MAX_SURVIVAL);
+                if random() >= survival {
+                    break;
+                }
+                throughput = throughput / survival;
+            }
+
+            // the cone keeps spreading from wherever it got to, which is only really right for mirrors but
+            // keeps textures seen in them filtered
+            ray = scatter.scattered.with_cone(record.footprint, ray.spread);
+            depth += 1;
+        }
+
+        color
+    }
+
+    // one shadow ray toward a uniformly picked light, weighted against the chance the bsdf would
+    // have sampled the same direction
+    fn sample_direct(&self, record: &HitRecord) -> Vec3 {
+        let lights = &self.built().lights;
+        if lights.is_empty() {
+            return Vec3::zeros();
+        }
+
+        let pick = ((random() * lights.len() as Float) as usize).min(lights.len() - 1);
+        let light = &lights[pick];
+        let Some(sample) = self.hittables[light.object].sample_toward(light.face, &record.point)
+        else {
+            return Vec3::zeros();
+        };
+
+        let direction = (sample.point - record.point).normalized();
+        let bsdf = record.material.evaluate(record, &direction);
+        if bsdf.x + bsdf.y + bsdf.z <= 0. || self.occluded(record, &sample.point) {
             return Vec3::zeros();
         }
 
+        let light_pdf = sample.pdf / lights.len() as Float;
+        let weight = power_heuristic(light_pdf, record.material.pdf(record, &direction));
+        light.emission.mul_component(&bsdf) * (weight / light_pdf)
+    }
+
+    // what sample_direct's pdf would have been for landing on the emitter in record from origin
+    fn light_pdf(&self, record: &HitRecord, origin: &Vec3) -> Float {
+        let lights = &self.built().lights;
+        if lights.is_empty() {
+            return 0.;
+        }
+        let pdf = self.hittables[record.object].pdf_toward(record.face, origin, &record.point);
+        pdf / lights.len() as Float
+    }
+
+    // aimed from the nudged origin, otherwise the ray would reach past point on anything closer
+    // than a unit away and hit the light it was sent toward
+    fn occluded(&self, record: &HitRecord, point: &Vec3) -> bool {
+        let origin = record.spawn_ray(*point - record.point).origin;
+        let shadow_ray = Ray::build(origin, *point - origin);
+        let mut shadow_record = HitRecord::new();
+        shadow_record.interval.max = 1. - SMALL;
+        self.hit(&shadow_ray, &mut shadow_record)
+    }
+
+    fn hit_object(&self, index: usize, ray: &Ray, record: &mut HitRecord) -> bool {
+        if !self.hittables[index].hit(ray, record) {
+            return false;
+        }
+        record.object = index;
+        true
+    }
+
+    // albedo and shading normal where the ray first lands, zero for both when it escapes
+    pub fn get_surface(&self, ray: &Ray) -> (Vec3, Vec3) {
         let mut record = HitRecord::new();
         if !self.hit(ray, &mut record) {
-            return sky_gradient(ray);
+            return (Vec3::zeros(), Vec3::zeros());
         }
+        record.resolve_textures(&self.images);
 
-        let mut scatter = ScatterRecord::new();
-        if record.material.scatter(&record, &mut scatter) {
-            return self.get_color(&scatter.scattered, depth - 1).mul_component(&scatter.attenuation);
+        (record.albedo, record.shading_normal)
+    }
+
+    pub fn hit(&self, ray: &Ray, record: &mut HitRecord) -> bool {
+        let hit = self.built().bvh.hit(ray, record, |index, record| self.hit_object(index, ray, record));
+        if hit {
+            record.footprint = ray.width + ray.spread * record.intersection_time * ray.direction.length();
         }
 
-        scatter.attenuation
+        hit
     }
 
-    pub fn hit(&self, ray: &Ray, record: &mut HitRecord) -> bool {
+    // reference path for checking the bvh against, gives identical hits just a lot slower
+    pub fn hit_linear(&self, ray: &Ray, record: &mut HitRecord) -> bool {
         let mut hit_anything = false;
         let mut temp_record = HitRecord::new();
-        for hittable in &self.hittables {
-            if hittable.hit(ray, &mut temp_record) {
+        for index in 0..self.hittables.len() {
+            if self.hit_object(index, ray, &mut temp_record) {
                 hit_anything = true;
                 *record = temp_record;
             }
@@ -63,3 +292,166 @@ impl Default for Scene {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::debug_scenes;
+    use crate::geometry::MeshFace;
+    use crate::materials::Glass;
+    use crate::materials::Lambertian;
+    use crate::materials::Metal;
+    use crate::materials::Source;
+    use crate::utils::seed_pixel;
+
+    // a bumpy grid floating in the middle of the room, its faces share edges and vertices which is
+    // where the bvh and the linear loop are most likely to disagree
+    fn grid_mesh() -> Mesh {
+        let size = 6;
+        let mut positions = Vec::new();
+        for row in 0..=size {
+            for column in 0..=size {
+                let (x, z) = (column as Float / size as Float, row as Float / size as Float);
+                let height = 2. + (x * 7.).sin() * (z * 5.).cos() * 0.4;
+                positions.push(Vec3::build(x * 3. - 1.5, height, z * 3. - 2.));
+            }
+        }
+        let mut faces = Vec::new();
+        for row in 0..size {
+            for column in 0..size {
+                let corner = row * (size + 1) + column;
+                let quad = [corner, corner + 1, corner + size + 2, corner + size + 1];
+                for positions in [[quad[0], quad[1], quad[2]], [quad[0], quad[2], quad[3]]] {
+                    faces.push(MeshFace { positions, normals: None, uvs: None, material: 0 });
+                }
+            }
+        }
+        let material = Material::Lambertian(Lambertian::build(Vec3::build(0.5, 0.5, 0.5)));
+        Mesh::build(positions, Vec::new(), Vec::new(), faces, vec![material])
+    }
+
+    #[test]
+    fn bvh_agrees_with_the_linear_loop() {
+        let mut scene = Scene::new();
+        debug_scenes::cornell_room(&mut scene);
+        scene.add_mesh(grid_mesh());
+        scene.build();
+        let bounds = scene.bounds();
+
+        seed_pixel(11, 0, 1);
+        for _ in 0..20_000 {
+            let offset = Vec3::random().mul_component(&bounds.diagonal());
+            let ray = Ray::build(bounds.centroid() + offset, Vec3::random_unit_vector());
+            let (mut bvh_record, mut linear_record) = (HitRecord::new(), HitRecord::new());
+            let bvh_hit = scene.hit(&ray, &mut bvh_record);
+            let linear_hit = scene.hit_linear(&ray, &mut linear_record);
+            // which object wins a tie on a shared edge is allowed to differ, where the ray stops isn't
+            assert_eq!(bvh_hit, linear_hit);
+            if bvh_hit {
+                assert_eq!(bvh_record.intersection_time, linear_record.intersection_time);
+            }
+        }
+    }
+
+    #[test]
+    fn adding_primitives_drops_the_built_light_list() {
+        let light = Material::Source(Source::build(Vec3::build(4., 4., 4.)));
+        let mut scene = Scene::new();
+        scene.add_sphere(Sphere::build(Vec3::zeros(), 1., light));
+        assert_eq!(scene.built().lights.len(), 1);
+
+        scene.add_sphere(Sphere::build(Vec3::build(3., 0., 0.), 1., light));
+        assert!(scene.built.get().is_none());
+        assert_eq!(scene.built().lights.len(), 2);
+    }
+
+    #[test]
+    fn scenes_build_themselves_on_the_first_hit() {
+        let mut scene = Scene::new();
+        debug_scenes::cornell_room(&mut scene);
+        let mut record = HitRecord::new();
+        assert!(scene.hit(&Ray::build(Vec3::build(0., 2., 7.), Vec3::build(0., 0., -1.)), &mut record));
+        assert!(scene.built.get().is_some());
+    }
+
+    #[test]
+    fn lights_close_above_a_surface_dont_shadow_themselves() {
+        let white = Material::Lambertian(Lambertian::build(Vec3::build(1., 1., 1.)));
+        let light = Material::Source(Source::build(Vec3::build(4., 4., 4.)));
+        for height in [0.05, 0.5, 0.8] {
+            let mut scene = Scene::new();
+            let corners = [Vec3::build(-2., 0., -2.), Vec3::build(2., 0., -2.), Vec3::build(0., 0., 2.)];
+            scene.add_triangle(Triangle::build(corners[0], corners[2], corners[1], white));
+            let lifted = corners.map(|corner| corner + Vec3::build(0., height, 0.));
+            scene.add_triangle(Triangle::build(lifted[0], lifted[1], lifted[2], light));
+
+            let mut record = HitRecord::new();
+            record.point = Vec3::build(0.3, 0., 0.2);
+            record.normal = Vec3::build(0., 1., 0.);
+            record.shading_normal = record.normal;
+            seed_pixel(5, 0, 1);
+            for _ in 0..1000 {
+                let sample = scene.hittables[1].sample_toward(0, &record.point).unwrap();
+                assert!(!scene.occluded(&record, &sample.point), "light {} above the floor", height);
+            }
+        }
+    }
+
+    #[test]
+    fn paths_end_inside_a_closed_white_room() {
+        let white = Material::Lambertian(Lambertian::build(Vec3::build(1., 1., 1.)));
+        let mut scene = Scene::new();
+        scene.add_sphere(Sphere::build(Vec3::zeros(), 5., white));
+        scene.add_sphere(Sphere::build(Vec3::build(2., 0., 0.), 1., white));
+
+        seed_pixel(3, 0, 1);
+        for _ in 0..200 {
+            let ray = Ray::build(Vec3::zeros(), Vec3::random_unit_vector());
+            let color = scene.get_color(&ray, None, 3);
+            assert_eq!((color.x, color.y, color.z), (0., 0., 0.));
+        }
+    }
+
+    // mean and standard error of the red channel over rays from in front of the scene
+    fn estimate(scene: &Scene, paths: usize) -> (Float, Float) {
+        seed_pixel(9, 0, 1);
+        let (mut sum, mut squares) = (0., 0.);
+        for _ in 0..paths {
+            let target = Vec3::build(random() * 4. - 2., random(), random() * 4. - 3.);
+            let origin = Vec3::build(0., 1.5, 5.);
+            let value = scene.get_color(&Ray::build(origin, target - origin), None, 3).x as f64;
+            sum += value;
+            squares += value * value;
+        }
+        let mean = sum / paths as f64;
+        let variance = squares / paths as f64 - mean * mean;
+        (mean as Float, (variance / paths as f64).sqrt() as Float)
+    }
+
+    #[test]
+    fn light_sampling_converges_to_plain_bsdf_sampling() {
+        let floor = Material::Lambertian(Lambertian::build(Vec3::build(0.6, 0.6, 0.6)));
+        let metal = Material::Metal(Metal::build(Vec3::build(0.9, 0.9, 0.9), 0.3));
+        let glass = Material::Glass(Glass::build(Vec3::build(1., 1., 1.), 1.5).with_roughness(0.3));
+        let light = Material::Source(Source::build(Vec3::build(4., 4., 4.)));
+
+        let mut scene = Scene::new();
+        scene.set_background(Vec3::zeros());
+        let [a, b, c, d] = [(-6., -6.), (6., -6.), (6., 6.), (-6., 6.)].map(|(x, z)| Vec3::build(x, 0., z));
+        scene.add_triangle(Triangle::build(a, c, b, floor));
+        scene.add_triangle(Triangle::build(a, d, c, floor));
+        // low enough over the floor that shadow rays toward it are shorter than a unit
+        let [a, b, c, d] =
+            [(-1., -3.), (1., -3.), (1., -1.), (-1., -1.)].map(|(x, z)| Vec3::build(x, 0.6, z));
+        scene.add_triangle(Triangle::build(a, b, c, light));
+        scene.add_triangle(Triangle::build(a, c, d, light));
+        scene.add_sphere(Sphere::build(Vec3::build(-0.8, 0.5, 0.5), 0.5, metal));
+        scene.add_sphere(Sphere::build(Vec3::build(0.8, 0.5, 0.5), 0.5, glass));
+
+        let (mis, mis_error) = estimate(&scene, 100_000);
+        scene.set_light_sampling(false);
+        let (bsdf, bsdf_error) = estimate(&scene, 100_000);
+        let tolerance = 4. * (mis_error * mis_error + bsdf_error * bsdf_error).sqrt();
+        assert!((mis - bsdf).abs() < tolerance, "mis {} against bsdf only {}", mis, bsdf);
+    }
+}
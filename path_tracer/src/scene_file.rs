@@ -0,0 +1,541 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+use crate::camera::Camera;
+use crate::camera::Projection;
+use crate::geometry::Sphere;
+use crate::geometry::Triangle;
+use crate::image::ImageError;
+use crate::image::ImageTable;
+use crate::materials::Glass;
+use crate::materials::Lambertian;
+use crate::materials::Material;
+use crate::materials::Metal;
+use crate::materials::Source;
+use crate::obj::ObjError;
+use crate::obj::ObjModel;
+use crate::scene::Scene;
+use crate::textures::Checker;
+use crate::textures::Filter;
+use crate::textures::ImageTexture;
+use crate::textures::Noise;
+use crate::textures::NormalMap;
+use crate::textures::Space;
+use crate::textures::Texture;
+use crate::textures::Wrap;
+use crate::tonemap::Operator;
+use crate::tonemap::Tonemap;
+use crate::utils::Float;
+use crate::utils::Int;
+use crate::utils::PI;
+use crate::vector::Vec3;
+
+#[derive(Debug)]
+pub enum SceneFileErrorKind {
+    Io(io::Error),
+    Parse(Box<toml::de::Error>),
+    UnknownMaterial(String),
+    NonPositive(&'static str),
+    Degenerate(&'static str),
+    Obj(Box<ObjError>),
+    Image(Box<ImageError>),
+}
+
+// entry names the table the problem is in, like `spheres[2]`, syntax and type errors come with
+// their own line and column from the toml parser instead
+#[derive(Debug)]
+pub struct SceneFileError {
+    pub path: PathBuf,
+    pub entry: Option<String>,
+    pub kind: SceneFileErrorKind,
+}
+
+impl SceneFileError {
+    fn build(path: &Path, entry: Option<String>, kind: SceneFileErrorKind) -> Self {
+        SceneFileError { path: path.to_path_buf(), entry, kind }
+    }
+}
+
+impl fmt::Display for SceneFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: ", self.path.display())?;
+        if let Some(entry) = &self.entry {
+            write!(f, "{}: ", entry)?;
+        }
+        match &self.kind {
+            SceneFileErrorKind::Io(error) => write!(f, "io failure: {}", error),
+            SceneFileErrorKind::Parse(error) => write!(f, "{}", error),
+            SceneFileErrorKind::UnknownMaterial(name) => write!(f, "unknown material `{}`", name),
+            SceneFileErrorKind::NonPositive(field) => write!(f, "`{}` must be greater than zero", field),
+            SceneFileErrorKind::Degenerate(problem) => write!(f, "{}", problem),
+            SceneFileErrorKind::Obj(error) => write!(f, "{}", error),
+            SceneFileErrorKind::Image(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for SceneFileError {}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneDescription {
+    #[serde(default)]
+    camera: CameraEntry,
+    #[serde(default)]
+    render: RenderEntry,
+    #[serde(default)]
+    materials: HashMap<String, MaterialEntry>,
+    #[serde(default)]
+    spheres: Vec<SphereEntry>,
+    #[serde(default)]
+    triangles: Vec<TriangleEntry>,
+    #[serde(default)]
+    quads: Vec<QuadEntry>,
+    #[serde(default)]
+    meshes: Vec<MeshEntry>,
+    // a uniform color where rays escape instead of the sky gradient
+    background: Option<[Float; 3]>,
+}
+
+// a target aims the camera with look at and replaces yaw and pitch, and is what stays sharp unless
+// focus_distance says otherwise
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct CameraEntry {
+    position: [Float; 3],
+    target: Option<[Float; 3]>,
+    up: [Float; 3],
+    yaw: Float,
+    pitch: Float,
+    fov: Float,
+    focal_length: Float,
+    projection: Projection,
+    aperture: Float,
+    focus_distance: Option<Float>,
+}
+
+impl Default for CameraEntry {
+    fn default() -> Self {
+        CameraEntry {
+            position: [0., 2., 7.],
+            target: None,
+            up: [0., 1., 0.],
+            yaw: PI / 2.,
+            pitch: 0.,
+            fov: 55.,
+            focal_length: 1.,
+            projection: Projection::default(),
+            aperture: 0.,
+            focus_distance: None,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RenderEntry {
+    width: Int,
+    height: Int,
+    samples: Int,
+    max_depth: Option<Int>,
+    roulette_depth: Int,
+    denoise: Int,
+    seed: Option<u64>,
+    exposure: Float,
+    tonemap: Operator,
+    white_point: Float,
+}
+
+impl Default for RenderEntry {
+    fn default() -> Self {
+        RenderEntry {
+            width: 1920,
+            height: 1080,
+            samples: 30,
+            max_depth: None,
+            roulette_depth: 3,
+            denoise: 1,
+            seed: None,
+            exposure: 0.,
+            tonemap: Operator::default(),
+            white_point: Tonemap::default().white_point,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum MaterialEntry {
+    Lambertian {
+        albedo: TextureEntry,
+        normal_map: Option<NormalMapEntry>,
+    },
+    Metal {
+        albedo: TextureEntry,
+        #[serde(default)]
+        roughness: Float,
+        normal_map: Option<NormalMapEntry>,
+    },
+    Glass {
+        #[serde(default = "white")]
+        albedo: TextureEntry,
+        refraction_index: Float,
+        #[serde(default)]
+        roughness: Float,
+    },
+    Source {
+        emission: [Float; 3],
+    },
+}
+
+// albedos are either a plain color or a table describing a pattern
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TextureEntry {
+    Color([Float; 3]),
+    Pattern(PatternEntry),
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum PatternEntry {
+    Checker {
+        even: [Float; 3],
+        odd: [Float; 3],
+        #[serde(default = "one")]
+        scale: Float,
+        #[serde(default)]
+        space: Space,
+    },
+    Noise {
+        low: [Float; 3],
+        high: [Float; 3],
+        #[serde(default = "one")]
+        scale: Float,
+        #[serde(default = "octaves")]
+        octaves: u32,
+    },
+    // the path is relative to the scene file, linear is for images that hold data instead of colors
+    Image {
+        path: PathBuf,
+        #[serde(default)]
+        wrap: Wrap,
+        #[serde(default)]
+        filter: Filter,
+        #[serde(default = "one")]
+        scale: Float,
+        #[serde(default)]
+        linear: bool,
+    },
+}
+
+// tangent maps want an image loaded with linear = true, bump heights have no natural size so the
+// strength that turns them into world units has to be given
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum NormalMapEntry {
+    Tangent {
+        map: TextureEntry,
+        #[serde(default = "one")]
+        strength: Float,
+    },
+    Bump {
+        height: TextureEntry,
+        strength: Float,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SphereEntry {
+    center: [Float; 3],
+    radius: Float,
+    material: String,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TriangleEntry {
+    vertices: [[Float; 3]; 3],
+    normals: Option<[[Float; 3]; 3]>,
+    uvs: Option<[[Float; 2]; 3]>,
+    material: String,
+}
+
+// split along the first diagonal, so the corners should go around the quad in order, the uvs run
+// from 0 to 1 along the first and last edge
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct QuadEntry {
+    vertices: [[Float; 3]; 4],
+    material: String,
+}
+
+// the path is relative to the scene file, material only covers faces the obj's own mtl doesn't
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MeshEntry {
+    path: PathBuf,
+    material: Option<String>,
+    #[serde(default = "one")]
+    scale: Float,
+    #[serde(default)]
+    offset: [Float; 3],
+}
+
+fn white() -> TextureEntry {
+    TextureEntry::Color([1., 1., 1.])
+}
+
+fn one() -> Float {
+    1.
+}
+
+fn octaves() -> u32 {
+    4
+}
+
+fn vec3(value: [Float; 3]) -> Vec3 {
+    Vec3::build(value[0], value[1], value[2])
+}
+
+impl TextureEntry {
+    fn to_texture(&self, directory: &Path, images: &mut ImageTable) -> Result<Texture, ImageError> {
+        Ok(match self {
+            TextureEntry::Color(color) => Texture::Solid(vec3(*color)),
+            TextureEntry::Pattern(PatternEntry::Checker { even, odd, scale, space }) => {
+                Texture::Checker(Checker::build(vec3(*even), vec3(*odd), *scale, *space))
+            }
+            TextureEntry::Pattern(PatternEntry::Noise { low, high, scale, octaves }) => {
+                Texture::Noise(Noise::build(vec3(*low), vec3(*high), *scale, *octaves))
+            }
+            TextureEntry::Pattern(PatternEntry::Image { path, wrap, filter, scale, linear }) => {
+                let image = images.load(&directory.join(path), *linear)?;
+                Texture::Image(ImageTexture::build(image, *wrap, *filter, *scale))
+            }
+        })
+    }
+}
+
+impl NormalMapEntry {
+    fn to_normal_map(&self, directory: &Path, images: &mut ImageTable) -> Result<NormalMap, ImageError> {
+        Ok(match self {
+            NormalMapEntry::Tangent { map, strength } => {
+                NormalMap::Tangent { map: map.to_texture(directory, images)?, strength: *strength }
+            }
+            NormalMapEntry::Bump { height, strength } => {
+                NormalMap::Bump { height: height.to_texture(directory, images)?, strength: *strength }
+            }
+        })
+    }
+}
+
+impl MaterialEntry {
+    fn to_material(&self, directory: &Path, images: &mut ImageTable) -> Result<Material, ImageError> {
+        let normal_map = |entry: &Option<NormalMapEntry>, images: &mut ImageTable| {
+            entry.as_ref().map(|entry| entry.to_normal_map(directory, images)).transpose()
+        };
+        Ok(match self {
+            MaterialEntry::Lambertian { albedo, normal_map: entry } => {
+                let albedo = albedo.to_texture(directory, images)?;
+                Material::Lambertian(Lambertian::build(albedo).with_normal_map(normal_map(entry, images)?))
+            }
+            MaterialEntry::Metal { albedo, roughness, normal_map: entry } => {
+                let albedo = albedo.to_texture(directory, images)?;
+                Material::Metal(Metal::build(albedo, *roughness).with_normal_map(normal_map(entry, images)?))
+            }
+            MaterialEntry::Glass { albedo, refraction_index, roughness } => {
+                let albedo = albedo.to_texture(directory, images)?;
+                Material::Glass(Glass::build(albedo, *refraction_index).with_roughness(*roughness))
+            }
+            MaterialEntry::Source { emission } => Material::Source(Source::build(vec3(*emission))),
+        })
+    }
+}
+
+fn camera_from(camera: &CameraEntry, render: &RenderEntry) -> Camera {
+    let aim = camera.target.map(|target| (vec3(camera.position), vec3(target), vec3(camera.up), camera.fov));
+    let focus_distance = match (camera.focus_distance, camera.target) {
+        (Some(distance), _) => distance,
+        (None, Some(target)) => (vec3(target) - vec3(camera.position)).length(),
+        (None, None) => 7.,
+    };
+    let mut camera = Camera {
+        height: render.height,
+        width: render.width,
+        position: vec3(camera.position),
+        fov: camera.fov,
+        focal_length: camera.focal_length,
+        projection: camera.projection,
+        aperture: camera.aperture,
+        focus_distance,
+        yaw: camera.yaw,
+        pitch: camera.pitch,
+        move_speed: 0.15,
+        rotation_speed: 0.03,
+        samples: render.samples,
+        max_depth: render.max_depth,
+        roulette_depth: render.roulette_depth,
+        denoise_iters: render.denoise,
+        seed: render.seed,
+        tonemap: Tonemap::build(render.exposure, render.tonemap, render.white_point),
+        world_up: vec3(camera.up).normalized(),
+        ..Default::default()
+    };
+    if let Some((eye, target, up, fov)) = aim {
+        camera.aim(eye, target, up, fov);
+    }
+    camera.build_params();
+
+    camera
+}
+
+// the camera and render settings a scene file gets when it leaves them out
+pub fn default_camera() -> Camera {
+    camera_from(&CameraEntry::default(), &RenderEntry::default())
+}
+
+pub fn load(path: &Path) -> Result<(Scene, Camera), SceneFileError> {
+    let error = |entry: Option<String>, kind| SceneFileError::build(path, entry, kind);
+    let source =
+        fs::read_to_string(path).map_err(|io_error| error(None, SceneFileErrorKind::Io(io_error)))?;
+    let description: SceneDescription = toml::from_str(&source)
+        .map_err(|parse_error| error(None, SceneFileErrorKind::Parse(Box::new(parse_error))))?;
+
+    let render = &description.render;
+    for (field, value) in [("width", render.width), ("height", render.height), ("samples", render.samples)] {
+        if value <= 0 {
+            return Err(error(Some("render".to_string()), SceneFileErrorKind::NonPositive(field)));
+        }
+    }
+    let camera = &description.camera;
+    let up = vec3(camera.up);
+    if up.near_zero() {
+        return Err(error(Some("camera".to_string()), SceneFileErrorKind::Degenerate("`up` can't be zero")));
+    }
+    if let Some(problem) =
+        camera.target.and_then(|target| Camera::aim_problem(vec3(camera.position), vec3(target), up))
+    {
+        return Err(error(Some("camera".to_string()), SceneFileErrorKind::Degenerate(problem)));
+    }
+    if camera.focus_distance.is_some_and(|distance| distance <= 0.) {
+        return Err(error(Some("camera".to_string()), SceneFileErrorKind::NonPositive("focus_distance")));
+    }
+    if render.white_point <= 0. {
+        return Err(error(Some("render".to_string()), SceneFileErrorKind::NonPositive("white_point")));
+    }
+
+    let directory = path.parent().unwrap_or(Path::new(""));
+    let mut scene = Scene::new();
+    let mut materials = HashMap::new();
+    for (name, entry) in &description.materials {
+        let material = entry.to_material(directory, scene.images_mut()).map_err(|image_error| {
+            error(Some(format!("materials.{}", name)), SceneFileErrorKind::Image(Box::new(image_error)))
+        })?;
+        materials.insert(name.as_str(), material);
+    }
+    let material = |entry: String, name: &str| {
+        materials
+            .get(name)
+            .copied()
+            .ok_or_else(|| error(Some(entry), SceneFileErrorKind::UnknownMaterial(name.to_string())))
+    };
+
+    if let Some(background) = description.background {
+        scene.set_background(vec3(background));
+    }
+    for (index, sphere) in description.spheres.iter().enumerate() {
+        let entry = format!("spheres[{}]", index);
+        if sphere.radius <= 0. {
+            return Err(error(Some(entry), SceneFileErrorKind::NonPositive("radius")));
+        }
+        let material = material(entry, &sphere.material)?;
+        scene.add_sphere(Sphere::build(vec3(sphere.center), sphere.radius, material));
+    }
+    for (index, triangle) in description.triangles.iter().enumerate() {
+        let material = material(format!("triangles[{}]", index), &triangle.material)?;
+        let [a, b, c] = triangle.vertices.map(vec3);
+        let built = match triangle.normals {
+            Some(normals) => {
+                Triangle::build_smooth(a, b, c, normals.map(|normal| vec3(normal).normalized()), material)
+            }
+            None => Triangle::build(a, b, c, material),
+        };
+        scene.add_triangle(match triangle.uvs {
+            Some(uvs) => built.with_uvs(uvs.map(|[u, v]| (u, v))),
+            None => built,
+        });
+    }
+    for (index, quad) in description.quads.iter().enumerate() {
+        let material = material(format!("quads[{}]", index), &quad.material)?;
+        let [a, b, c, d] = quad.vertices.map(vec3);
+        scene.add_triangle(Triangle::build(a, b, c, material).with_uvs([(0., 0.), (1., 0.), (1., 1.)]));
+        scene.add_triangle(Triangle::build(a, c, d, material).with_uvs([(0., 0.), (1., 1.), (0., 1.)]));
+    }
+    for (index, mesh) in description.meshes.iter().enumerate() {
+        let entry = format!("meshes[{}]", index);
+        let fallback = match &mesh.material {
+            Some(name) => material(entry.clone(), name)?,
+            None => Material::default(),
+        };
+        let mesh_path = directory.join(&mesh.path);
+        let mut model = ObjModel::load(&mesh_path, scene.images_mut())
+            .map_err(|obj_error| error(Some(entry), SceneFileErrorKind::Obj(Box::new(obj_error))))?;
+        model.transform(mesh.scale, vec3(mesh.offset));
+        scene.add_obj(&model, fallback);
+    }
+
+    Ok((scene, camera_from(&description.camera, render)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn focus_distance(camera: &str) -> Float {
+        let camera: CameraEntry = toml::from_str(camera).unwrap();
+        camera_from(&camera, &RenderEntry::default()).focus_distance
+    }
+
+    #[test]
+    fn focus_lands_on_the_target_unless_given() {
+        assert_eq!(focus_distance("position = [0.0, 2.0, 7.0]\ntarget = [0.0, 2.0, 3.0]"), 4.);
+        assert_eq!(
+            focus_distance("position = [0.0, 2.0, 7.0]\ntarget = [0.0, 2.0, 3.0]\nfocus_distance = 2.5"),
+            2.5
+        );
+        assert_eq!(focus_distance("position = [0.0, 2.0, 7.0]"), 7.);
+    }
+
+    #[test]
+    fn example_scenes_load() {
+        let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenes");
+        let mut loaded = 0;
+        for entry in fs::read_dir(directory).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_some_and(|extension| extension == "toml") {
+                load(&path).unwrap_or_else(|error| panic!("{}", error));
+                loaded += 1;
+            }
+        }
+        assert!(loaded > 0);
+    }
+
+    #[test]
+    fn errors_name_the_entry_at_fault() {
+        let path = std::env::temp_dir().join(format!("path_tracer_bad_{}.toml", std::process::id()));
+        let source = "[materials.white]\ntype = \"lambertian\"\nalbedo = [1.0, 1.0, 1.0]\n\n\
+            [[spheres]]\ncenter = [0.0, 0.0, 0.0]\nradius = 1.0\nmaterial = \"white\"\n\n\
+            [[spheres]]\ncenter = [0.0, 0.0, 0.0]\nradius = 1.0\nmaterial = \"chalk\"\n";
+        fs::write(&path, source).unwrap();
+        let message = load(&path).err().map(|error| error.to_string());
+        fs::remove_file(&path).unwrap();
+        assert!(message.is_some_and(|message| message.ends_with("spheres[1]: unknown material `chalk`")));
+    }
+}
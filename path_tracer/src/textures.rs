@@ -0,0 +1,368 @@
+use serde::Deserialize;
+
+use crate::image::Image;
+use crate::image::ImageTable;
+use crate::ray_hit::HitRecord;
+use crate::utils::Float;
+use crate::vector::Vec3;
+
+#[derive(Clone, Copy)]
+pub enum Texture {
+    Solid(Vec3),
+    Checker(Checker),
+    Image(ImageTexture),
+    Noise(Noise),
+}
+
+impl Texture {
+    pub fn value(&self, record: &HitRecord, images: &ImageTable) -> Vec3 {
+        match self {
+            Texture::Solid(color) => *color,
+            Texture::Checker(checker) => checker.value(record),
+            Texture::Image(image) => image.value(record, images),
+            Texture::Noise(noise) => noise.value(&record.point),
+        }
+    }
+}
+
+impl From<Vec3> for Texture {
+    fn from(color: Vec3) -> Self {
+        Texture::Solid(color)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Space {
+    #[default]
+    Uv,
+    World,
+}
+
+// scale is how many squares fit in one unit of world space, or across the whole uv square
+#[derive(Clone, Copy)]
+pub struct Checker {
+    pub even: Vec3,
+    pub odd: Vec3,
+    pub scale: Float,
+    pub space: Space,
+}
+
+impl Checker {
+    pub fn build(even: Vec3, odd: Vec3, scale: Float, space: Space) -> Self {
+        Checker { even, odd, scale, space }
+    }
+
+    fn value(&self, record: &HitRecord) -> Vec3 {
+        let cell = |coordinate: Float| (coordinate * self.scale).floor() as i64;
+        let sum = match self.space {
+            Space::Uv => cell(record.uv.0) + cell(record.uv.1),
+            Space::World => cell(record.point.x) + cell(record.point.y) + cell(record.point.z),
+        };
+        if sum.rem_euclid(2) == 0 {
+            self.even
+        }
+        else {
+            self.odd
+        }
+    }
+}
+
+// what happens to uvs outside of [0, 1]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Wrap {
+    #[default]
+    Repeat,
+    Clamp,
+    Mirror,
+}
+
+impl Wrap {
+    fn apply(&self, coordinate: i64, size: usize) -> usize {
+        let size = size as i64;
+        let wrapped = match self {
+            Wrap::Repeat => coordinate.rem_euclid(size),
+            Wrap::Clamp => coordinate.clamp(0, size - 1),
+            Wrap::Mirror => {
+                let folded = coordinate.rem_euclid(2 * size);
+                if folded < size {
+                    folded
+                }
+                else {
+                    2 * size - 1 - folded
+                }
+            }
+        };
+        wrapped as usize
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Filter {
+    Nearest,
+    #[default]
+    Bilinear,
+    // bilinear on the two mip levels closest to the ray cone's footprint, blended between them
+    Trilinear,
+}
+
+// image indexes the scene's image table, v runs bottom to top like in obj files, scale tiles the
+// image that many times across the uvs
+#[derive(Clone, Copy)]
+pub struct ImageTexture {
+    pub image: usize,
+    pub wrap: Wrap,
+    pub filter: Filter,
+    pub scale: Float,
+}
+
+impl ImageTexture {
+    pub fn build(image: usize, wrap: Wrap, filter: Filter, scale: Float) -> Self {
+        ImageTexture { image, wrap, filter, scale }
+    }
+
+    fn value(&self, record: &HitRecord, images: &ImageTable) -> Vec3 {
+        let map = images.get(self.image);
+        let (u, v) = (record.uv.0 * self.scale, record.uv.1 * self.scale);
+        let base = map.base();
+        match self.filter {
+            Filter::Nearest => {
+                let x = self.wrap.apply((u * base.width as Float).floor() as i64, base.width);
+                let y = self.wrap.apply(((1. - v) * base.height as Float).floor() as i64, base.height);
+                base.texel(x, y)
+            }
+            Filter::Bilinear => self.bilinear(base, u, v),
+            Filter::Trilinear => {
+                let texels = record.footprint * record.uv_density * self.scale;
+                let level = (texels * base.width.max(base.height) as Float).max(1.).log2();
+                let level = level.min((map.levels.len() - 1) as Float);
+                let lower = level.floor() as usize;
+                let upper = (lower + 1).min(map.levels.len() - 1);
+                let t = level - lower as Float;
+                self.bilinear(&map.levels[lower], u, v) * (1. - t)
+                    + self.bilinear(&map.levels[upper], u, v) * t
+            }
+        }
+    }
+
+    // between the four texel centers around the sample
+    fn bilinear(&self, image: &Image, u: Float, v: Float) -> Vec3 {
+        let x = u * image.width as Float - 0.5;
+        let y = (1. - v) * image.height as Float - 0.5;
+        let (left, top) = (x.floor(), y.floor());
+        let (tx, ty) = (x - left, y - top);
+
+        let texel = |dx: i64, dy: i64| {
+            let x = self.wrap.apply(left as i64 + dx, image.width);
+            let y = self.wrap.apply(top as i64 + dy, image.height);
+            image.texel(x, y)
+        };
+        let upper = texel(0, 0) * (1. - tx) + texel(1, 0) * tx;
+        let lower = texel(0, 1) * (1. - tx) + texel(1, 1) * tx;
+        upper * (1. - ty) + lower * ty
+    }
+}
+
+// tilts the shading normal, either with tangent space normals packed into rgb the way baking tools
+// write them, blue pointing away from the surface, or with the slopes of a height texture whose
+// average channel strength scales into world units
+#[derive(Clone, Copy)]
+pub enum NormalMap {
+    Tangent { map: Texture, strength: Float },
+    Bump { height: Texture, strength: Float },
+}
+
+// how far along u and v heights get sampled again to find their slope
+const BUMP_DELTA: Float = 1. / 1024.;
+// the lowest a mapped normal may sit over the geometric surface, as the cosine between the two
+const MIN_HEIGHT: Float = 0.01;
+
+impl NormalMap {
+    pub fn apply(&self, record: &mut HitRecord, images: &ImageTable) {
+        let normal = record.shading_normal;
+        let perturbed = match *self {
+            NormalMap::Tangent { map, strength } => {
+                let texel = map.value(record, images) * 2. - Vec3::build(1., 1., 1.);
+                let tangent = (record.tangent - normal * normal.inner_product(&record.tangent)).normalized();
+                // mirrored uvs flip the bitangent, so it follows dp/dv instead of the handedness
+                let mut bitangent = normal.cross_product(&tangent);
+                if bitangent.inner_product(&record.bitangent) < 0. {
+                    bitangent = -bitangent;
+                }
+                tangent * (texel.x * strength) + bitangent * (texel.y * strength) + normal * texel.z
+            }
+            NormalMap::Bump { height, strength } => {
+                let base = average(height.value(record, images));
+                let slope = |du: Float, dv: Float| {
+                    let mut shifted = *record;
+                    shifted.uv = (record.uv.0 + du, record.uv.1 + dv);
+                    shifted.point = record.point + record.tangent * du + record.bitangent * dv;
+                    (average(height.value(&shifted, images)) - base) / BUMP_DELTA * strength
+                };
+                let tangent = record.tangent + normal * slope(BUMP_DELTA, 0.);
+                let bitangent = record.bitangent + normal * slope(0., BUMP_DELTA);
+                let bumped = tangent.cross_product(&bitangent);
+                if bumped.inner_product(&normal) < 0. {
+                    -bumped
+                }
+                else {
+                    bumped
+                }
+            }
+        };
+
+        // degenerate tangents at poles and on squashed uvs leave nothing to normalize
+        if !perturbed.length().is_normal() {
+            return;
+        }
+        // a strong map can tilt the normal past the actual surface, which the bsdfs have no answer
+        // for, so it gets pulled back to just above the geometric horizon
+        let perturbed = perturbed.normalized();
+        let geometric = record.normal;
+        let height = perturbed.inner_product(&geometric);
+        let sideways = perturbed - geometric * height;
+        record.shading_normal = if height >= MIN_HEIGHT {
+            perturbed
+        }
+        else if sideways.near_zero() {
+            geometric
+        }
+        else {
+            sideways.normalized() * (1. - MIN_HEIGHT * MIN_HEIGHT).sqrt() + geometric * MIN_HEIGHT
+        };
+    }
+}
+
+fn average(color: Vec3) -> Float {
+    (color.x + color.y + color.z) / 3.
+}
+
+// fractal brownian motion over perlin noise in world space, blended between low and high
+#[derive(Clone, Copy)]
+pub struct Noise {
+    pub low: Vec3,
+    pub high: Vec3,
+    pub scale: Float,
+    pub octaves: u32,
+}
+
+impl Noise {
+    pub fn build(low: Vec3, high: Vec3, scale: Float, octaves: u32) -> Self {
+        Noise { low, high, scale, octaves }
+    }
+
+    fn value(&self, point: &Vec3) -> Vec3 {
+        let t = (0.5 * (fbm(*point * self.scale, self.octaves) + 1.)).clamp(0., 1.);
+        self.low * (1. - t) + self.high * t
+    }
+}
+
+// each octave doubles the frequency and halves the amplitude, normalized back to about [-1, 1]
+pub fn fbm(point: Vec3, octaves: u32) -> Float {
+    let (mut sum, mut amplitude, mut total, mut point) = (0., 1., 0., point);
+    for _ in 0..octaves.max(1) {
+        sum += perlin(point) * amplitude;
+        total += amplitude;
+        amplitude *= 0.5;
+        point = point * 2.;
+    }
+    sum / total
+}
+
+// ken perlin's improved noise, with his reference permutation so the pattern never changes between
+// runs or machines, the table repeats every 256 cells which is what the masking does
+pub fn perlin(point: Vec3) -> Float {
+    let (floor_x, floor_y, floor_z) = (point.x.floor(), point.y.floor(), point.z.floor());
+    let (x, y, z) = (point.x - floor_x, point.y - floor_y, point.z - floor_z);
+    let cell = |floor: Float| (floor as i64 & 255) as usize;
+    let (cell_x, cell_y, cell_z) = (cell(floor_x), cell(floor_y), cell(floor_z));
+    let hash = |i: usize| PERMUTATION[i & 255] as usize;
+
+    let a = hash(cell_x) + cell_y;
+    let (aa, ab) = (hash(a) + cell_z, hash(a + 1) + cell_z);
+    let b = hash(cell_x + 1) + cell_y;
+    let (ba, bb) = (hash(b) + cell_z, hash(b + 1) + cell_z);
+
+    let (u, v, w) = (fade(x), fade(y), fade(z));
+    let lerp = |t: Float, a: Float, b: Float| a + t * (b - a);
+    lerp(
+        w,
+        lerp(
+            v,
+            lerp(u, gradient(hash(aa), x, y, z), gradient(hash(ba), x - 1., y, z)),
+            lerp(u, gradient(hash(ab), x, y - 1., z), gradient(hash(bb), x - 1., y - 1., z)),
+        ),
+        lerp(
+            v,
+            lerp(u, gradient(hash(aa + 1), x, y, z - 1.), gradient(hash(ba + 1), x - 1., y, z - 1.)),
+            lerp(
+                u,
+                gradient(hash(ab + 1), x, y - 1., z - 1.),
+                gradient(hash(bb + 1), x - 1., y - 1., z - 1.),
+            ),
+        ),
+    )
+}
+
+fn fade(t: Float) -> Float {
+    t * t * t * (t * (t * 6. - 15.) + 10.)
+}
+
+// dot product with one of twelve edge directions of a cube, picked by the low bits of the hash
+fn gradient(hash: usize, x: Float, y: Float, z: Float) -> Float {
+    let h = hash & 15;
+    let u = if h < 8 { x } else { y };
+    let v = if h < 4 {
+        y
+    }
+    else if h == 12 || h == 14 {
+        x
+    }
+    else {
+        z
+    };
+    (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+}
+
+const PERMUTATION: [u8; 256] = [
+    151, 160, 137, 91, 90, 15, 131, 13, 201, 95, 96, 53, 194, 233, 7, 225, 140, 36, 103, 30, 69, 142, 8, 99,
+    37, 240, 21, 10, 23, 190, 6, 148, 247, 120, 234, 75, 0, 26, 197, 62, 94, 252, 219, 203, 117, 35, 11, 32,
+    57, 177, 33, 88, 237, 149, 56, 87, 174, 20, 125, 136, 171, 168, 68, 175, 74, 165, 71, 134, 139, 48, 27,
+    166, 77, 146, 158, 231, 83, 111, 229, 122, 60, 211, 133, 230, 220, 105, 92, 41, 55, 46, 245, 40, 244,
+    102, 143, 54, 65, 25, 63, 161, 1, 216, 80, 73, 209, 76, 132, 187, 208, 89, 18, 169, 200, 196, 135, 130,
+    116, 188, 159, 86, 164, 100, 109, 198, 173, 186, 3, 64, 52, 217, 226, 250, 124, 123, 5, 202, 38, 147,
+    118, 126, 255, 82, 85, 212, 207, 206, 59, 227, 47, 16, 58, 17, 182, 189, 28, 42, 223, 183, 170, 213, 119,
+    248, 152, 2, 44, 154, 163, 70, 221, 153, 101, 155, 167, 43, 172, 9, 129, 22, 39, 253, 19, 98, 108, 110,
+    79, 113, 224, 232, 178, 185, 112, 104, 218, 246, 97, 228, 251, 34, 242, 193, 238, 210, 144, 12, 191, 179,
+    162, 241, 81, 51, 145, 235, 249, 14, 239, 107, 49, 192, 214, 31, 181, 199, 106, 157, 184, 84, 204, 176,
+    115, 121, 50, 45, 127, 4, 150, 254, 138, 236, 205, 93, 222, 114, 67, 29, 24, 72, 243, 141, 128, 195, 78,
+    66, 215, 61, 156, 180,
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mapped_normals_stay_above_the_surface() {
+        let mut record = HitRecord::new();
+        record.normal = Vec3::build(0., 1., 0.);
+        record.shading_normal = record.normal;
+        record.tangent = Vec3::build(1., 0., 0.);
+        record.bitangent = Vec3::build(0., 0., 1.);
+
+        // texels pointing sideways and straight through the surface, and one that's fine as it is
+        for (texel, strength) in
+            [((1., 0.5, 0.5), 10.), ((1., 0.5, 0.), 1.), ((0.5, 0.5, 0.), 1.), ((0.6, 0.5, 1.), 1.)]
+        {
+            let map = Texture::Solid(Vec3::build(texel.0, texel.1, texel.2));
+            let mut mapped = record;
+            NormalMap::Tangent { map, strength }.apply(&mut mapped, &ImageTable::new());
+            let normal = mapped.shading_normal;
+            assert!((normal.length() - 1.).abs() < 1e-5);
+            assert!(normal.inner_product(&record.normal) >= MIN_HEIGHT - 1e-6, "{:?}", texel);
+        }
+    }
+}
@@ -0,0 +1,191 @@
+use serde::Deserialize;
+
+use crate::utils::Float;
+use crate::vector::Vec3;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Operator {
+    #[default]
+    Clamp,
+    Reinhard,
+    ExtendedReinhard,
+    AcesFitted,
+    Filmic,
+}
+
+impl Operator {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().replace('-', "_").as_str() {
+            "clamp" => Some(Operator::Clamp),
+            "reinhard" => Some(Operator::Reinhard),
+            "extended_reinhard" => Some(Operator::ExtendedReinhard),
+            "aces_fitted" | "aces" => Some(Operator::AcesFitted),
+            "filmic" => Some(Operator::Filmic),
+            _ => None,
+        }
+    }
+}
+
+// exposure is in stops, white point is the radiance extended reinhard maps to full white
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tonemap {
+    pub exposure: Float,
+    pub operator: Operator,
+    pub white_point: Float,
+}
+
+impl Default for Tonemap {
+    fn default() -> Self {
+        Tonemap { exposure: 0., operator: Operator::Clamp, white_point: 4. }
+    }
+}
+
+impl Tonemap {
+    pub fn build(exposure: Float, operator: Operator, white_point: Float) -> Self {
+        Tonemap { exposure, operator, white_point }
+    }
+
+    // linear radiance to linear display values in [0, 1]
+    pub fn map(&self, color: Vec3) -> Vec3 {
+        let color = per_channel(color * self.exposure.exp2(), |channel| channel.max(0.));
+        let mapped = match self.operator {
+            Operator::Clamp => color,
+            Operator::Reinhard => per_channel(color, |channel| channel / (1. + channel)),
+            Operator::ExtendedReinhard => {
+                let white = self.white_point * self.white_point;
+                per_channel(color, |channel| channel * (1. + channel / white) / (1. + channel))
+            }
+            Operator::AcesFitted => aces_fitted(color),
+            Operator::Filmic => per_channel(color, |channel| hable(2. * channel) / hable(HABLE_WHITE)),
+        };
+
+        per_channel(mapped, |channel| channel.clamp(0., 1.))
+    }
+
+    // what actually gets quantized, tonemapped and then srgb encoded
+    pub fn display(&self, color: Vec3) -> Vec3 {
+        per_channel(self.map(color), srgb_encode)
+    }
+}
+
+pub fn srgb_encode(linear: Float) -> Float {
+    if linear <= 0.0031308 {
+        linear * 12.92
+    }
+    else {
+        1.055 * linear.powf(1. / 2.4) - 0.055
+    }
+}
+
+pub fn srgb_decode(encoded: Float) -> Float {
+    if encoded <= 0.04045 {
+        encoded / 12.92
+    }
+    else {
+        ((encoded + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn per_channel(color: Vec3, f: impl Fn(Float) -> Float) -> Vec3 {
+    Vec3::build(f(color.x), f(color.y), f(color.z))
+}
+
+// stephen hill's fit of the aces reference rendering and output transforms, going through the
+// aces working space and back with the matrices in rows
+const ACES_INPUT: [[Float; 3]; 3] =
+    [[0.59719, 0.35458, 0.04823], [0.07600, 0.90834, 0.01566], [0.02840, 0.13383, 0.83777]];
+const ACES_OUTPUT: [[Float; 3]; 3] =
+    [[1.60475, -0.53108, -0.07367], [-0.10208, 1.10813, -0.00605], [-0.00327, -0.07276, 1.07602]];
+
+fn aces_fitted(color: Vec3) -> Vec3 {
+    let color = mul_matrix(&ACES_INPUT, color);
+    let color = per_channel(color, |channel| {
+        let a = channel * (channel + 0.0245786) - 0.000090537;
+        let b = channel * (0.983729 * channel + 0.432951) + 0.238081;
+        a / b
+    });
+
+    mul_matrix(&ACES_OUTPUT, color)
+}
+
+fn mul_matrix(matrix: &[[Float; 3]; 3], color: Vec3) -> Vec3 {
+    let row = |row: &[Float; 3]| row[0] * color.x + row[1] * color.y + row[2] * color.z;
+    Vec3::build(row(&matrix[0]), row(&matrix[1]), row(&matrix[2]))
+}
+
+// john hable's uncharted 2 curve, normalized so the linear white below lands on 1
+const HABLE_WHITE: Float = 11.2;
+
+fn hable(x: Float) -> Float {
+    let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+    (x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f) - e / f
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OPERATORS: [Operator; 5] = [
+        Operator::Clamp,
+        Operator::Reinhard,
+        Operator::ExtendedReinhard,
+        Operator::AcesFitted,
+        Operator::Filmic,
+    ];
+
+    fn gray(value: Float) -> Vec3 {
+        Vec3::build(value, value, value)
+    }
+
+    #[test]
+    fn srgb_round_trips() {
+        for step in 0..=1000 {
+            let value = step as Float / 1000.;
+            assert!((srgb_decode(srgb_encode(value)) - value).abs() < 1e-5, "{}", value);
+            assert!((srgb_encode(srgb_decode(value)) - value).abs() < 1e-5, "{}", value);
+        }
+        assert_eq!((srgb_encode(0.), srgb_decode(0.)), (0., 0.));
+        assert!((srgb_encode(1.) - 1.).abs() < 1e-6 && (srgb_decode(1.) - 1.).abs() < 1e-6);
+    }
+
+    #[test]
+    fn srgb_pieces_meet_at_the_breakpoint() {
+        let (linear, encoded) = (0.0031308, 0.04045);
+        assert!((srgb_encode(linear) - encoded).abs() < 1e-5);
+        assert!((srgb_decode(encoded) - linear).abs() < 1e-6);
+        // the power curve picks up right where the linear segment leaves off
+        assert!((1.055 * linear.powf(1. / 2.4) - 0.055 - linear * 12.92).abs() < 1e-4);
+        assert!((((encoded + 0.055) / 1.055).powf(2.4) - encoded / 12.92).abs() < 1e-6);
+    }
+
+    #[test]
+    fn operators_map_black_to_black_and_rise_within_range() {
+        for operator in OPERATORS {
+            let tonemap = Tonemap::build(0., operator, 4.);
+            // hable's curve only cancels to zero up to rounding
+            assert!(tonemap.map(gray(0.)).x < 1e-6, "{:?}", operator);
+
+            let mut previous = 0.;
+            for step in 1..=400 {
+                let mapped = tonemap.map(gray(step as Float * 0.05)).x;
+                assert!(mapped >= previous, "{:?} drops at {}", operator, step as Float * 0.05);
+                assert!((0. ..=1.).contains(&mapped), "{:?} leaves [0, 1] with {}", operator, mapped);
+                previous = mapped;
+            }
+        }
+    }
+
+    #[test]
+    fn curves_only_reach_white_where_they_should() {
+        let map = |operator, value| Tonemap::build(0., operator, 4.).map(gray(value)).x;
+        assert_eq!(map(Operator::Clamp, 0.25), 0.25);
+        assert!(map(Operator::Reinhard, 100.) < 1.);
+        assert!((map(Operator::ExtendedReinhard, 4.) - 1.).abs() < 1e-6);
+        assert!(map(Operator::ExtendedReinhard, 3.9) < 1.);
+        assert!((map(Operator::Filmic, HABLE_WHITE / 2.) - 1.).abs() < 1e-5);
+        // exposure is in stops
+        let brighter = Tonemap::build(1., Operator::Clamp, 4.).map(gray(0.25)).x;
+        assert_eq!(brighter, 0.5);
+    }
+}
@@ -1,14 +1,15 @@
-use std::fs::File;
-use std::io;
-use std::io::BufWriter;
-use std::io::Write;
+use std::cell::RefCell;
 
+use rand::rngs::SmallRng;
 use rand::Rng;
+use rand::SeedableRng;
 
 use crate::ray_hit::Ray;
 use crate::vector::Vec3;
 
 pub const INFIN: Float = f32::INFINITY;
+pub const PI: Float = std::f32::consts::PI;
+pub const SQRT_2: Float = std::f32::consts::SQRT_2;
 pub const SMALL: Float = 1e-4;
 pub const BACKGROUND_COLOR: Vec3 = Vec3::build(0.5, 0.7, 1.);
 
@@ -53,32 +54,37 @@ pub fn sky_gradient(ray: &Ray) -> Vec3 {
     Vec3::build(1., 1., 1.) * (1. - alpha) + BACKGROUND_COLOR * alpha
 }
 
-pub fn random() -> Float {
-    let mut rng = rand::rng();
-    rng.random::<Float>()
+// weight for a sample taken with pdf when other is the pdf the competing strategy would have had
+pub fn power_heuristic(pdf: Float, other: Float) -> Float {
+    let (pdf, other) = (pdf * pdf, other * other);
+    if pdf + other <= 0. {
+        return 0.;
+    }
+    pdf / (pdf + other)
 }
 
-pub fn packed_color(color: Vec3) -> u32 {
-    let red = ((color.x * 255.) as u8) as u32;
-    let green = ((color.y * 255.) as u8) as u32;
-    let blue = ((color.z * 255.) as u8) as u32;
-
-    (red << 16) | (green << 8) | blue
+thread_local! {
+    static RNG: RefCell<SmallRng> = RefCell::new(SmallRng::from_rng(&mut rand::rng()));
 }
 
-pub fn unpack_color(color: u32) -> Vec3 {
-    let red = ((color >> 16) & 0xff) as f32 / 255.;
-    let green = ((color >> 8) & 0xff) as f32 / 255.;
-    let blue = ((color) & 0xff) as f32 / 255.;
+pub fn random() -> Float {
+    RNG.with(|rng| rng.borrow_mut().random::<Float>())
+}
 
-    Vec3::build(red, green, blue)
+// a pixel is always rendered start to finish by one thread, so reseeding from the seed, the pixel
+// and how many samples it already holds gives the same image no matter how rayon splits the work
+pub fn seed_pixel(seed: u64, index: usize, samples: u32) {
+    let mixed = seed
+        ^ (index as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15)
+        ^ (samples as u64).wrapping_mul(0xc2b2_ae3d_27d4_eb4f);
+    RNG.with(|rng| *rng.borrow_mut() = SmallRng::seed_from_u64(mixed));
 }
 
-pub fn write_pixel_gammcorr(writer: &mut BufWriter<&mut File>, color: Vec3) -> io::Result<()> {
-    let red = (color.x.sqrt() * 255.999) as u8;
-    let green = (color.y.sqrt() * 255.999) as u8;
-    let blue = (color.z.sqrt() * 255.999) as u8;
-    write!(writer, "{} {} {} ", red, green, blue)?;
+// expects display values, see Tonemap::display
+pub fn packed_color(color: Vec3) -> u32 {
+    let red = (color.x.clamp(0., 1.) * 255.999) as u32;
+    let green = (color.y.clamp(0., 1.) * 255.999) as u32;
+    let blue = (color.z.clamp(0., 1.) * 255.999) as u32;
 
-    Ok(())
+    (red << 16) | (green << 8) | blue
 }
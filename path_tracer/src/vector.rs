@@ -17,7 +17,6 @@ pub struct Vec3 {
     pub z: Float,
 }
 
-#[allow(dead_code)]
 impl Vec3 {
     pub const TOLERANCE: f32 = SMALL;
 
@@ -61,6 +60,26 @@ impl Vec3 {
         Vec3::build(self.x * other.x, self.y * other.y, self.z * other.z)
     }
 
+    pub fn max_component(&self) -> Float {
+        self.x.max(self.y).max(self.z)
+    }
+
+    pub fn component_min(&self, other: &Self) -> Self {
+        Vec3::build(self.x.min(other.x), self.y.min(other.y), self.z.min(other.z))
+    }
+
+    pub fn component_max(&self, other: &Self) -> Self {
+        Vec3::build(self.x.max(other.x), self.y.max(other.y), self.z.max(other.z))
+    }
+
+    pub fn axis(&self, axis: usize) -> Float {
+        match axis {
+            0 => self.x,
+            1 => self.y,
+            _ => self.z,
+        }
+    }
+
     pub fn rotate_x_inplace(&mut self, angle: Float) {
         let Vec3 { x, y, z } = *self;
         let (sin, cos) = angle.sin_cos();
@@ -103,16 +122,28 @@ impl Vec3 {
         Vec3::build(x * cos + y * -sin, x * sin + y * cos, z)
     }
 
+    // rejecting everything outside the inscribed sphere keeps the directions uniform, the corners
+    // of the cube would otherwise be picked more often
     pub fn random_unit_vector() -> Self {
         loop {
             let candidate = Vec3::random();
             let squared_magnitude = candidate.inner_product(&candidate);
-            if Self::TOLERANCE < squared_magnitude {
+            if Self::TOLERANCE < squared_magnitude && squared_magnitude <= 0.25 {
                 return candidate / squared_magnitude.sqrt();
             }
         }
     }
 
+    // rejection sampling again, z is always zero
+    pub fn random_in_unit_disk() -> Self {
+        loop {
+            let candidate = Vec3::build(2. * random() - 1., 2. * random() - 1., 0.);
+            if candidate.inner_product(&candidate) < 1. {
+                return candidate;
+            }
+        }
+    }
+
     pub fn random_on_hemisphere(norm: &Vec3) -> Self {
         let candidate = Self::random_unit_vector();
         if candidate.inner_product(norm) > 0. {
@@ -123,6 +154,17 @@ impl Vec3 {
         }
     }
 
+    // two unit vectors perpendicular to self and each other, self has to be normalized
+    // branchless construction from duff et al. 2017
+    pub fn orthonormal_basis(&self) -> (Self, Self) {
+        let sign = 1_f32.copysign(self.z);
+        let a = -1. / (sign + self.z);
+        let b = self.x * self.y * a;
+        let tangent = Vec3::build(1. + sign * self.x * self.x * a, sign * b, -sign * self.x);
+        let bitangent = Vec3::build(b, sign + self.y * self.y * a, -self.y);
+        (tangent, bitangent)
+    }
+
     pub fn reflect_around(&self, axis: &Vec3) -> Self {
         *self - *axis * (2. * self.inner_product(axis))
     }